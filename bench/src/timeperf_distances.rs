@@ -0,0 +1,58 @@
+//! Provides the benchmark of batch Hamming distance computation,
+//! comparing `CodeInt::distances` with a loop of `CodeInt::distance`.
+use mih_rs::{CodeInt, WideCode};
+use rand::{thread_rng, Rng};
+use std::any::type_name;
+use std::time;
+
+const SIZE: usize = 1_000_000;
+const NUM_QUERIES: usize = 100;
+
+fn main() {
+    #[cfg(debug_assertions)]
+    println!("Debugging enabled");
+
+    perf_test(gen_random_codes(SIZE, |rng| rng.gen::<u64>()));
+    println!();
+    perf_test(gen_random_codes(SIZE, |rng| WideCode::<1>(rng.gen())));
+    println!();
+    perf_test(gen_random_codes(SIZE, |rng| WideCode::<4>(rng.gen())));
+    println!();
+    perf_test(gen_random_codes(SIZE, |rng| WideCode::<8>(rng.gen())));
+}
+
+fn perf_test<T: CodeInt>(codes: Vec<T>) {
+    println!("*** perf_test<{}> ***", type_name::<T>());
+
+    let mut dists = vec![0; codes.len()];
+
+    let ins = time::Instant::now();
+    for qcode in &codes[..NUM_QUERIES] {
+        for (code, dist) in codes.iter().zip(dists.iter_mut()) {
+            *dist = code.distance(qcode) as u32;
+        }
+    }
+    let elapsed_ms = ins.elapsed().as_millis() as f64;
+    let checksum: u64 = dists.iter().map(|&d| d as u64).sum();
+    println!("distance:\t{} ms/query", elapsed_ms / NUM_QUERIES as f64);
+
+    let ins = time::Instant::now();
+    for qcode in &codes[..NUM_QUERIES] {
+        T::distances(&codes, qcode, &mut dists);
+    }
+    let elapsed_ms = ins.elapsed().as_millis() as f64;
+    assert_eq!(dists.iter().map(|&d| d as u64).sum::<u64>(), checksum);
+    println!("distances:\t{} ms/query", elapsed_ms / NUM_QUERIES as f64);
+}
+
+fn gen_random_codes<T, F>(size: usize, mut gen: F) -> Vec<T>
+where
+    F: FnMut(&mut rand::rngs::ThreadRng) -> T,
+{
+    let mut rng = thread_rng();
+    let mut codes: Vec<T> = Vec::with_capacity(size);
+    for _ in 0..size {
+        codes.push(gen(&mut rng));
+    }
+    codes
+}
@@ -0,0 +1,72 @@
+//! Provides the benchmark of incremental insertion for MIH,
+//! showing how the insertion time grows with the number of stored codes.
+use rand::distributions::{Distribution, Standard};
+use rand::{thread_rng, Rng};
+use std::any::type_name;
+use std::time;
+
+const INIT_SIZE: usize = 10_000;
+const SIZES: [usize; 3] = [100_000, 1_000_000, 10_000_000];
+const NUM_INSERTS: usize = 10_000;
+
+fn main() {
+    #[cfg(debug_assertions)]
+    println!("Debugging enabled");
+
+    perf_test::<u32>();
+    println!();
+    perf_test::<u64>();
+}
+
+fn perf_test<T: mih_rs::CodeInt + Copy>()
+where
+    Standard: Distribution<T>,
+{
+    println!("*** perf_test<{}> ***", type_name::<T>());
+
+    let codes = gen_random_codes::<T>(SIZES[SIZES.len() - 1] + NUM_INSERTS);
+
+    // The blocks are chosen for INIT_SIZE codes, and the index grows by insertions.
+    let mut index = mih_rs::Index::new(codes[..INIT_SIZE].to_vec()).unwrap();
+    println!("Blocks chosen for N={}", INIT_SIZE);
+
+    for &size in &SIZES {
+        let beg = index.codes().len();
+        for &code in &codes[beg..size] {
+            index.insert(code).unwrap();
+        }
+
+        let ins = time::Instant::now();
+        for &code in &codes[size..size + NUM_INSERTS] {
+            index.insert(code).unwrap();
+        }
+        let elapsed_us = ins.elapsed().as_micros() as f64;
+        println!("N={}:\t{} us/insert", size, elapsed_us / NUM_INSERTS as f64);
+    }
+
+    // For comparison, the blocks are chosen for the final size.
+    let size = SIZES[SIZES.len() - 1];
+    let mut index = mih_rs::Index::new(codes[..size].to_vec()).unwrap();
+    let ins = time::Instant::now();
+    for &code in &codes[size..size + NUM_INSERTS] {
+        index.insert(code).unwrap();
+    }
+    let elapsed_us = ins.elapsed().as_micros() as f64;
+    println!(
+        "N={} (blocks chosen for N):\t{} us/insert",
+        size,
+        elapsed_us / NUM_INSERTS as f64
+    );
+}
+
+pub fn gen_random_codes<T>(size: usize) -> Vec<T>
+where
+    Standard: Distribution<T>,
+{
+    let mut rng = thread_rng();
+    let mut codes: Vec<T> = Vec::with_capacity(size);
+    for _ in 0..size {
+        codes.push(rng.gen::<T>());
+    }
+    codes
+}
@@ -25,7 +25,7 @@ fn main() {
     }
 }
 
-fn perf_test<T: mih_rs::CodeInt>(codes: Vec<T>, qcodes: Vec<T>) {
+fn perf_test<T: mih_rs::CodeInt + Copy>(codes: Vec<T>, qcodes: Vec<T>) {
     println!("*** perf_test<{}> ***", type_name::<T>());
 
     for &size in &SIZES {
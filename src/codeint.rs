@@ -1,15 +1,107 @@
 use std::io::{Read, Write};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use num_traits::int::PrimInt;
-use num_traits::{FromPrimitive, ToPrimitive};
+use std::ops::BitXor;
+
+use crate::popcnt;
 
 /// Generic trait of binary codes.
-pub trait CodeInt: PrimInt + FromPrimitive + ToPrimitive + Popcnt + Default {
+///
+/// The trait defines only the operations that multi-index hashing needs,
+/// so that it can be implemented for user-defined code types such as bitsets or SIMD vectors.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use anyhow::Result;
+/// use mih_rs::{CodeInt, Index};
+///
+/// // A 256-bit code stored on the heap.
+/// #[derive(Clone, PartialEq, Eq, Debug)]
+/// struct BoxedCode(Box<[u64; 4]>);
+///
+/// impl CodeInt for BoxedCode {
+///     fn dimensions() -> usize {
+///         256
+///     }
+///
+///     fn extract(&self, beg: usize, end: usize) -> u64 {
+///         (beg..end).fold(0, |x, i| x | ((self.0[i / 64] >> (i % 64)) & 1) << (i - beg))
+///     }
+///
+///     fn distance(&self, other: &Self) -> usize {
+///         (0..4).map(|i| (self.0[i] ^ other.0[i]).count_ones() as usize).sum()
+///     }
+///
+///     fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
+///         for x in self.0.iter() {
+///             writer.write_all(&x.to_le_bytes())?;
+///         }
+///         Ok(())
+///     }
+///
+///     fn deserialize_from<R: Read>(mut reader: R) -> Result<Self> {
+///         let mut words = [0; 4];
+///         for x in words.iter_mut() {
+///             let mut buf = [0; 8];
+///             reader.read_exact(&mut buf)?;
+///             *x = u64::from_le_bytes(buf);
+///         }
+///         Ok(Self(Box::new(words)))
+///     }
+/// }
+///
+/// let codes = vec![
+///     BoxedCode(Box::new([0, 0, 0, 0])),
+///     BoxedCode(Box::new([1, 0, 0, 0])),
+///     BoxedCode(Box::new([u64::MAX, 0, 0, 0])),
+/// ];
+/// let index = Index::new(codes).unwrap();
+/// let mut searcher = index.range_searcher();
+/// assert_eq!(searcher.run(BoxedCode(Box::new([0, 0, 0, 0])), 1), vec![0, 1]);
+/// ```
+pub trait CodeInt: Clone {
+    /// Returns the number of bits in a code.
     fn dimensions() -> usize;
+
+    /// Returns the number of bits held by this code.
+    ///
+    /// The default implementation returns [`CodeInt::dimensions()`].
+    /// A type whose width is given at runtime, such as [`DynCode`], returns the width of each code,
+    /// and the bits at or above it are regarded as zeros.
+    fn width(&self) -> usize {
+        Self::dimensions()
+    }
+
+    /// Extracts the bits in `[beg, end)` as an integer, where `end - beg` is at most 64.
+    /// The `beg`-th bit becomes the least significant bit of the result.
+    fn extract(&self, beg: usize, end: usize) -> u64;
+
+    /// Computes the Hamming distance to another code, i.e., the popcount of their XOR.
+    fn distance(&self, other: &Self) -> usize;
+
+    /// Computes the Hamming distances between each code in `codes` and `qcode`,
+    /// storing them in `dists` of the same length as `codes`.
+    ///
+    /// The default implementation calls [`CodeInt::distance()`] for each code.
+    /// The implementations for `u64` and [`WideCode`] use AVX2 or AVX-512 VPOPCNTDQ
+    /// if the running CPU supports them.
+    ///
+    /// # Panics
+    ///
+    /// It will panic if the lengths of `codes` and `dists` are different.
+    fn distances(codes: &[Self], qcode: &Self, dists: &mut [u32]) {
+        assert_eq!(codes.len(), dists.len());
+        for (code, dist) in codes.iter().zip(dists.iter_mut()) {
+            *dist = code.distance(qcode) as u32;
+        }
+    }
+
     fn serialize_into<W: Write>(&self, writer: W) -> Result<()>;
     fn deserialize_from<R: Read>(reader: R) -> Result<Self>;
 }
@@ -19,6 +111,15 @@ impl CodeInt for u8 {
         8
     }
 
+    fn extract(&self, beg: usize, end: usize) -> u64 {
+        debug_assert!(beg <= end && end <= 8);
+        ((*self as u64) >> beg) & low_mask(end - beg)
+    }
+
+    fn distance(&self, other: &Self) -> usize {
+        (*self ^ *other).popcnt() as usize
+    }
+
     fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
         writer.write_u8(*self)?;
         Ok(())
@@ -35,6 +136,15 @@ impl CodeInt for u16 {
         16
     }
 
+    fn extract(&self, beg: usize, end: usize) -> u64 {
+        debug_assert!(beg <= end && end <= 16);
+        ((*self as u64) >> beg) & low_mask(end - beg)
+    }
+
+    fn distance(&self, other: &Self) -> usize {
+        (*self ^ *other).popcnt() as usize
+    }
+
     fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
         writer.write_u16::<LittleEndian>(*self)?;
         Ok(())
@@ -51,6 +161,15 @@ impl CodeInt for u32 {
         32
     }
 
+    fn extract(&self, beg: usize, end: usize) -> u64 {
+        debug_assert!(beg <= end && end <= 32);
+        ((*self as u64) >> beg) & low_mask(end - beg)
+    }
+
+    fn distance(&self, other: &Self) -> usize {
+        (*self ^ *other).popcnt() as usize
+    }
+
     fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
         writer.write_u32::<LittleEndian>(*self)?;
         Ok(())
@@ -67,6 +186,19 @@ impl CodeInt for u64 {
         64
     }
 
+    fn extract(&self, beg: usize, end: usize) -> u64 {
+        debug_assert!(beg <= end && end <= 64);
+        (*self >> beg) & low_mask(end - beg)
+    }
+
+    fn distance(&self, other: &Self) -> usize {
+        (*self ^ *other).popcnt() as usize
+    }
+
+    fn distances(codes: &[Self], qcode: &Self, dists: &mut [u32]) {
+        popcnt::xor_popcnt_batch(codes, std::slice::from_ref(qcode), dists);
+    }
+
     fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
         writer.write_u64::<LittleEndian>(*self)?;
         Ok(())
@@ -78,6 +210,251 @@ impl CodeInt for u64 {
     }
 }
 
+impl CodeInt for u128 {
+    fn dimensions() -> usize {
+        128
+    }
+
+    fn extract(&self, beg: usize, end: usize) -> u64 {
+        debug_assert!(beg <= end && end <= 128);
+        ((*self >> beg) as u64) & low_mask(end - beg)
+    }
+
+    fn distance(&self, other: &Self) -> usize {
+        (*self ^ *other).popcnt() as usize
+    }
+
+    fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_u128::<LittleEndian>(*self)?;
+        Ok(())
+    }
+
+    fn deserialize_from<R: Read>(mut reader: R) -> Result<Self> {
+        let x = reader.read_u128::<LittleEndian>()?;
+        Ok(x)
+    }
+}
+
+/// Binary code of `64 * W` bits represented by an array of `W` words.
+///
+/// The `i`-th bit of the code is the `(i % 64)`-th bit of the `(i / 64)`-th word,
+/// counting from the least significant bit.
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::{hamdist, WideCode};
+///
+/// let x = WideCode([0b0011, 0, 0, u64::MAX]);
+/// let y = WideCode([0b0101, 0, 0, 0]);
+/// assert_eq!(hamdist(x, y), 66);
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[repr(transparent)]
+pub struct WideCode<const W: usize>(pub [u64; W]);
+
+impl<const W: usize> Default for WideCode<W> {
+    fn default() -> Self {
+        Self([0; W])
+    }
+}
+
+impl<const W: usize> WideCode<W> {
+    /// Makes a code from a byte array such as a digest of a content hash.
+    ///
+    /// The `i`-th bit of the code is the `(i % 8)`-th bit of the `(i / 8)`-th byte,
+    /// counting from the least significant bit. In other words, each group of eight bytes
+    /// is read as a little-endian word. If `bytes` is shorter than `8 * W`,
+    /// the remaining high bits are filled with zeros.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when `bytes` is longer than `8 * W`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::WideCode;
+    ///
+    /// let code = WideCode::<2>::from_bytes(&[0x01, 0x02, 0, 0, 0, 0, 0, 0x80, 0xFF]).unwrap();
+    /// assert_eq!(code, WideCode([0x8000_0000_0000_0201, 0xFF]));
+    /// assert!(WideCode::<1>::from_bytes(&[0; 9]).is_err());
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if 8 * W < bytes.len() {
+            return Err(anyhow!(
+                "The number of bytes {} must not be more than {}",
+                bytes.len(),
+                8 * W
+            ));
+        }
+        let mut words = [0; W];
+        for (i, &x) in bytes.iter().enumerate() {
+            words[i / 8] |= (x as u64) << (8 * (i % 8));
+        }
+        Ok(Self(words))
+    }
+
+    /// Returns the byte array of the code in the bit order of [`WideCode::from_bytes()`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+}
+
+impl<const W: usize> From<[u64; W]> for WideCode<W> {
+    fn from(words: [u64; W]) -> Self {
+        Self(words)
+    }
+}
+
+impl<const W: usize> BitXor for WideCode<W> {
+    type Output = Self;
+
+    fn bitxor(mut self, rhs: Self) -> Self {
+        for (x, y) in self.0.iter_mut().zip(rhs.0.iter()) {
+            *x ^= y;
+        }
+        self
+    }
+}
+
+impl<const W: usize> CodeInt for WideCode<W> {
+    fn dimensions() -> usize {
+        64 * W
+    }
+
+    fn extract(&self, beg: usize, end: usize) -> u64 {
+        extract_words(&self.0, beg, end)
+    }
+
+    fn distance(&self, other: &Self) -> usize {
+        popcnt::xor_popcnt(&self.0, &other.0)
+    }
+
+    fn distances(codes: &[Self], qcode: &Self, dists: &mut [u32]) {
+        // SAFETY: WideCode<W> is a transparent wrapper of [u64; W].
+        let words =
+            unsafe { std::slice::from_raw_parts(codes.as_ptr() as *const u64, codes.len() * W) };
+        popcnt::xor_popcnt_batch(words, &qcode.0, dists);
+    }
+
+    fn serialize_into<Wr: Write>(&self, mut writer: Wr) -> Result<()> {
+        for &x in &self.0 {
+            writer.write_u64::<LittleEndian>(x)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize_from<R: Read>(mut reader: R) -> Result<Self> {
+        let mut words = [0; W];
+        for x in words.iter_mut() {
+            *x = reader.read_u64::<LittleEndian>()?;
+        }
+        Ok(Self(words))
+    }
+}
+
+/// Binary code whose number of bits is given at runtime, represented by a boxed slice of words.
+///
+/// The `i`-th bit of the code is the `(i % 64)`-th bit of the `(i / 64)`-th word,
+/// counting from the least significant bit, as in [`WideCode`].
+/// The words beyond the slice are regarded as zeros, e.g., in [`CodeInt::extract()`] and [`CodeInt::distance()`].
+///
+/// Since the type does not know the width, [`CodeInt::dimensions()`] returns the upper bound
+/// [`DynCode::MAX_DIMENSIONS`]. An index of the codes should be built with the actual width,
+/// e.g., with [`Index::from_words()`](crate::Index::from_words) or [`Index::with_dimensions()`](crate::Index::with_dimensions).
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::{hamdist, DynCode};
+///
+/// let x = DynCode::from(vec![0b0011, u64::MAX]);
+/// let y = DynCode::from(vec![0b0101]);
+/// assert_eq!(hamdist(x, y), 66);
+/// ```
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct DynCode(pub Box<[u64]>);
+
+impl DynCode {
+    /// The maximum number of bits in a code.
+    pub const MAX_DIMENSIONS: usize = 1 << 16;
+
+    /// Gets the words of the code.
+    pub fn words(&self) -> &[u64] {
+        &self.0
+    }
+}
+
+impl From<Vec<u64>> for DynCode {
+    fn from(words: Vec<u64>) -> Self {
+        Self(words.into_boxed_slice())
+    }
+}
+
+impl From<&[u64]> for DynCode {
+    fn from(words: &[u64]) -> Self {
+        Self(words.into())
+    }
+}
+
+impl CodeInt for DynCode {
+    fn dimensions() -> usize {
+        Self::MAX_DIMENSIONS
+    }
+
+    fn width(&self) -> usize {
+        64 * self.0.len()
+    }
+
+    fn extract(&self, beg: usize, end: usize) -> u64 {
+        if end <= self.width() {
+            return extract_words(&self.0, beg, end);
+        }
+        let word = |i: usize| self.0.get(i).copied().unwrap_or(0);
+        let (pos, off) = (beg / 64, beg % 64);
+        let mut x = word(pos) >> off;
+        if off != 0 {
+            x |= word(pos + 1) << (64 - off);
+        }
+        x & low_mask(end - beg)
+    }
+
+    fn distance(&self, other: &Self) -> usize {
+        let (x, y) = if self.0.len() <= other.0.len() {
+            (&self.0, &other.0)
+        } else {
+            (&other.0, &self.0)
+        };
+        let (head, tail) = y.split_at(x.len());
+        popcnt::xor_popcnt(x, head) + tail.iter().map(|w| w.count_ones() as usize).sum::<usize>()
+    }
+
+    fn serialize_into<Wr: Write>(&self, mut writer: Wr) -> Result<()> {
+        writer.write_u64::<LittleEndian>(self.0.len() as u64)?;
+        for &x in self.0.iter() {
+            writer.write_u64::<LittleEndian>(x)?;
+        }
+        Ok(())
+    }
+
+    fn deserialize_from<R: Read>(mut reader: R) -> Result<Self> {
+        let len = reader.read_u64::<LittleEndian>()? as usize;
+        if Self::MAX_DIMENSIONS.div_ceil(64) < len {
+            return Err(anyhow!(
+                "The number of words {} must not be more than {}",
+                len,
+                Self::MAX_DIMENSIONS.div_ceil(64)
+            ));
+        }
+        let mut words = vec![0; len];
+        for x in words.iter_mut() {
+            *x = reader.read_u64::<LittleEndian>()?;
+        }
+        Ok(Self::from(words))
+    }
+}
+
 /// Generic trait for pop-countable integers.
 pub trait Popcnt {
     fn popcnt(&self) -> u32;
@@ -106,3 +483,143 @@ impl Popcnt for u64 {
         self.count_ones()
     }
 }
+
+impl Popcnt for u128 {
+    fn popcnt(&self) -> u32 {
+        self.count_ones()
+    }
+}
+
+impl<const W: usize> Popcnt for WideCode<W> {
+    fn popcnt(&self) -> u32 {
+        self.0.iter().map(|x| x.count_ones()).sum()
+    }
+}
+
+/// Extracts the bits in `[beg, end)` of a code packed in `words`.
+#[inline(always)]
+pub(crate) fn extract_words(words: &[u64], beg: usize, end: usize) -> u64 {
+    debug_assert!(beg <= end && end - beg <= 64);
+    debug_assert!(end <= 64 * words.len());
+
+    let dim = end - beg;
+
+    let pos = beg / 64;
+    let off = beg % 64;
+
+    let mut x = words[pos] >> off;
+    if off != 0 && 64 < off + dim {
+        x |= words[pos + 1] << (64 - off);
+    }
+    x & low_mask(dim)
+}
+
+#[inline(always)]
+pub(crate) const fn low_mask(dim: usize) -> u64 {
+    if dim == 64 {
+        u64::MAX
+    } else {
+        (1 << dim) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_extract_works() {
+        let code = WideCode([0xFEDCBA9876543210, 0x0123456789ABCDEF]);
+        assert_eq!(code.extract(0, 16), 0x3210);
+        assert_eq!(code.extract(60, 68), 0xFF);
+        assert_eq!(code.extract(56, 72), 0xEFFE);
+        assert_eq!(code.extract(32, 96), 0x89ABCDEFFEDCBA98);
+        assert_eq!(code.extract(64, 128), 0x0123456789ABCDEF);
+        assert_eq!(code.extract(120, 128), 0x01);
+        assert_eq!(code.extract(7, 7), 0);
+    }
+
+    #[test]
+    fn dyn_extract_works() {
+        let code = DynCode::from(vec![0xFEDCBA9876543210, 0x0123456789ABCDEF]);
+        assert_eq!(code.width(), 128);
+        assert_eq!(code.extract(56, 72), 0xEFFE);
+        assert_eq!(code.extract(64, 128), 0x0123456789ABCDEF);
+        assert_eq!(code.extract(120, 136), 0x01);
+        assert_eq!(code.extract(128, 192), 0);
+        assert_eq!(code.extract(1000, 1010), 0);
+    }
+
+    #[test]
+    fn int_extract_works() {
+        assert_eq!(0xA5u8.extract(0, 8), 0xA5);
+        assert_eq!(0xA5u8.extract(4, 8), 0xA);
+        assert_eq!(0xFEDCu16.extract(4, 12), 0xED);
+        assert_eq!(u64::MAX.extract(0, 64), u64::MAX);
+        assert_eq!((1u128 << 100).extract(90, 110), 1 << 10);
+    }
+
+    #[test]
+    fn distance_works() {
+        assert_eq!(0b1100u8.distance(&0b1010), 2);
+        assert_eq!(u128::MAX.distance(&0), 128);
+        let x = WideCode([0b0011, 0, 0, u64::MAX]);
+        let y = WideCode([0b0101, 0, 0, 0]);
+        assert_eq!(x.distance(&y), 66);
+        let x = DynCode::from(vec![0b0011, 0, 0, u64::MAX]);
+        let y = DynCode::from(vec![0b0101]);
+        assert_eq!(x.distance(&y), 66);
+        assert_eq!(y.distance(&x), 66);
+    }
+
+    #[test]
+    fn distances_works() {
+        let codes: Vec<u64> = (0..20).map(|i| (1 << i) - 1).collect();
+        let mut dists = vec![0; 20];
+        u64::distances(&codes, &0, &mut dists);
+        assert_eq!(dists, (0..20).collect::<Vec<u32>>());
+
+        let codes: Vec<WideCode<1>> = codes.into_iter().map(|c| WideCode([c])).collect();
+        let mut dists = vec![0; 20];
+        WideCode::distances(&codes, &WideCode([0]), &mut dists);
+        assert_eq!(dists, (0..20).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    #[should_panic]
+    fn distances_with_short_dists_fail() {
+        let codes = vec![0u64; 20];
+        let mut dists = vec![0; 10];
+        u64::distances(&codes, &0, &mut dists);
+    }
+
+    #[test]
+    #[should_panic]
+    fn wide_distances_with_short_dists_fail() {
+        let codes = vec![WideCode([0u64]); 20];
+        let mut dists = vec![0; 10];
+        WideCode::distances(&codes, &WideCode([0]), &mut dists);
+    }
+
+    #[test]
+    fn wide_io_works() {
+        let code = WideCode([1, 2, 3, u64::MAX]);
+        let mut data = vec![];
+        code.serialize_into(&mut data).unwrap();
+        assert_eq!(data.len(), 32);
+        let other = WideCode::<4>::deserialize_from(&data[..]).unwrap();
+        assert_eq!(code, other);
+    }
+
+    #[test]
+    fn dyn_io_works() {
+        let code = DynCode::from(vec![1, 2, u64::MAX]);
+        let mut data = vec![];
+        code.serialize_into(&mut data).unwrap();
+        assert_eq!(data.len(), 32);
+        let other = DynCode::deserialize_from(&data[..]).unwrap();
+        assert_eq!(code, other);
+        assert!(DynCode::deserialize_from(&data[..31]).is_err());
+        assert!(DynCode::deserialize_from(&u64::MAX.to_le_bytes()[..]).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{DynCode, Index};
+
+/// Multi-index hashing for binary codes whose length is given at runtime.
+///
+/// [`DynIndex`] is [`Index`] of [`DynCode`], so it provides the same neighbor searches and serialization.
+/// It can be built from binary codes packed in a flat vector of 64-bit words
+/// with [`Index::from_words()`], and searched with packed query codes
+/// with [`RangeSearcher::run_words()`](crate::index::RangeSearcher::run_words)
+/// and [`TopkSearcher::run_words()`](crate::index::TopkSearcher::run_words).
+///
+/// # Arguments
+///
+/// Each code of `num_dimensions` bits occupies `ceil(num_dimensions / 64)` consecutive words.
+/// The `i`-th bit of a code is the `(i % 64)`-th bit of its `(i / 64)`-th word,
+/// counting from the least significant bit. The unused high bits of the last word must be zero.
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::DynIndex;
+///
+/// // Database of 96-bit codes, each packed in two words
+/// let codes: Vec<u64> = vec![
+///     u64::MAX, 0xFFFFFFFE, // #zeros = 1
+///     u64::MAX, 0xFFFF0000, // #zeros = 16
+///     u64::MAX - 0b11, 0xFFFFFFFF, // #zeros = 2
+///     0, 0, // #zeros = 96
+/// ];
+///
+/// // Query code
+/// let qcode: Vec<u64> = vec![u64::MAX, 0xFFFFFFFF]; // #zeros = 0
+///
+/// // Construct the index
+/// let index = DynIndex::from_words_with_blocks(codes, 96, 8).unwrap();
+///
+/// // Find the ids of neighbor codes whose Hamming distances are within 2
+/// let mut searcher = index.range_searcher();
+/// let answers = searcher.run_words(&qcode, 2).unwrap();
+/// assert_eq!(answers, vec![0, 2]);
+///
+/// // Find the ids of the top-3 nearest neighbor codes
+/// let mut searcher = index.topk_searcher();
+/// let answers = searcher.run_words(&qcode, 3).unwrap();
+/// assert_eq!(answers, vec![0, 2, 1]);
+///
+/// // Serialization/Deserialization
+/// let mut data = vec![];
+/// index.serialize_into(&mut data).unwrap();
+/// let other = DynIndex::deserialize_from(&data[..]).unwrap();
+/// assert_eq!(index, other);
+/// ```
+pub type DynIndex = Index<DynCode>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codeint::low_mask;
+    use crate::ls;
+
+    use rand::{thread_rng, Rng};
+
+    fn gen_random_codes(size: usize, num_dimensions: usize) -> Vec<u64> {
+        let num_words = num_dimensions.div_ceil(64);
+        let last_mask = low_mask(num_dimensions - 64 * (num_words - 1));
+        let mut rng = thread_rng();
+        let mut codes = vec![0; size * num_words];
+        rng.fill(&mut codes[..]);
+        for code in codes.chunks_mut(num_words) {
+            code[num_words - 1] &= last_mask;
+        }
+        codes
+    }
+
+    fn do_range_search(num_dimensions: usize) {
+        let codes = gen_random_codes(10000, num_dimensions);
+        let index = DynIndex::from_words(codes, num_dimensions).unwrap();
+        assert_eq!(index.num_dimensions(), num_dimensions);
+        let mut searcher = index.range_searcher();
+
+        for rad in 0..6 {
+            for qi in (0..10000).step_by(100) {
+                let qcode = index.codes()[qi].clone();
+                let ans1 = ls::range_search(index.codes(), qcode.clone(), rad);
+                let ans2 = searcher.run_words(qcode.words(), rad).unwrap();
+                assert_eq!(ans1, ans2);
+            }
+        }
+    }
+
+    fn do_topk_search(num_dimensions: usize) {
+        let codes = gen_random_codes(10000, num_dimensions);
+        let index = DynIndex::from_words(codes, num_dimensions).unwrap();
+        let mut searcher = index.topk_searcher();
+
+        for &topk in &[1, 10, 100] {
+            for qi in (0..10000).step_by(100) {
+                let qcode = index.codes()[qi].clone();
+                let mut dists = ls::exhaustive_search(index.codes(), qcode.clone());
+                dists.sort_by_key(|x| x.1);
+                let max_dist = dists[topk - 1].1 as usize;
+
+                let ans = searcher.run_words(qcode.words(), topk).unwrap();
+                assert_eq!(ans.len(), topk);
+                for &id in ans {
+                    assert!(
+                        crate::hamdist(index.codes()[id as usize].clone(), qcode.clone())
+                            <= max_dist
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn range_search_d96_works() {
+        do_range_search(96);
+    }
+
+    #[test]
+    fn range_search_d200_works() {
+        do_range_search(200);
+    }
+
+    #[test]
+    fn topk_search_d96_works() {
+        do_topk_search(96);
+    }
+
+    #[test]
+    fn topk_search_d200_works() {
+        do_topk_search(200);
+    }
+
+    #[test]
+    fn topk_search_more_than_codes_works() {
+        let index = DynIndex::from_words_with_blocks(vec![0, 1, 3], 8, 2).unwrap();
+        let mut searcher = index.topk_searcher();
+        assert_eq!(searcher.run_words(&[0], 5).unwrap(), &[0, 1, 2]);
+        assert_eq!(searcher.run_words(&[3], 3).unwrap(), &[2, 1, 0]);
+
+        let mut searcher = index.range_searcher();
+        assert_eq!(searcher.run_words(&[0], 64).unwrap(), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn invalid_codes_fail() {
+        assert!(DynIndex::from_words(vec![], 96).is_err());
+        assert!(DynIndex::from_words(vec![0; 3], 96).is_err());
+        assert!(DynIndex::from_words(vec![0, 1 << 32], 96).is_err());
+        assert!(DynIndex::from_words(vec![0, 1 << 31], 96).is_ok());
+        assert!(DynIndex::from_words(vec![0; 2], 0).is_err());
+        assert!(DynIndex::from_words(vec![0; 2], DynCode::MAX_DIMENSIONS + 1).is_err());
+    }
+
+    #[test]
+    fn run_words_fails() {
+        let index = DynIndex::from_words(vec![0, 0, 1, 0, u64::MAX, 0xFF], 72).unwrap();
+        let mut searcher = index.range_searcher();
+        assert!(searcher.run_words(&[0], 1).is_err());
+        assert!(searcher.run_words(&[0, 0, 0], 1).is_err());
+        assert_eq!(searcher.run_words(&[0, 0], 1).unwrap(), &[0, 1]);
+        // A query with a bit at or above the number of dimensions finds nothing.
+        assert!(searcher.run_words(&[0, 1 << 8], 72).unwrap().is_empty());
+
+        let mut searcher = index.topk_searcher();
+        assert!(searcher.run_words(&[0], 1).is_err());
+        assert!(searcher.run_words(&[0, 0, 0], 1).is_err());
+        assert_eq!(searcher.run_words(&[u64::MAX, 0xFF], 1).unwrap(), &[2]);
+    }
+
+    #[test]
+    fn serialize_works() {
+        let codes = gen_random_codes(10000, 160);
+        let index = DynIndex::from_words(codes, 160).unwrap();
+
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+        let other = DynIndex::deserialize_from(&data[..]).unwrap();
+
+        assert_eq!(index, other);
+    }
+}
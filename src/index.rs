@@ -1,6 +1,10 @@
+mod builder;
 mod ops;
-mod siggen;
-mod sparsehash;
+pub(crate) mod siggen;
+pub(crate) mod sparsehash;
+
+pub use builder::{BitPermutation, BlockWidths, IndexBuilder};
+pub(crate) use ops::has_high_bits;
 
 use crate::CodeInt;
 
@@ -60,11 +64,56 @@ use crate::CodeInt;
 /// ```
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Index<T: CodeInt> {
+    num_dimensions: usize,
     num_blocks: usize,
     codes: Vec<T>,
     tables: Vec<sparsehash::Table>,
-    masks: Vec<T>,
     begs: Vec<usize>,
+    bits: Vec<usize>,
+    runs: Vec<Vec<Run>>,
+    removed: Vec<bool>,
+    num_removed: usize,
+}
+
+/// Contiguous bits `[beg, end)` of a code placed from the `pos`-th bit of a chunk.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Run {
+    beg: usize,
+    end: usize,
+    pos: usize,
+}
+
+/// Neighbor code found by a search, with its Hamming distance to the query code.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub struct Neighbor {
+    /// Id of the code.
+    pub id: u32,
+    /// Hamming distance to the query code.
+    pub dist: u32,
+}
+
+/// Order of the neighbors returned by [`RangeSearcher::run_neighbors()`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum NeighborOrder {
+    /// Sorted by ids.
+    #[default]
+    Id,
+    /// Sorted by distances, where ties are sorted by ids.
+    Distance,
+}
+
+/// Policy to select codes tied at the K-th distance in [`TopkSearcher`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TiePolicy {
+    /// Selects whichever ties are found first during the enumeration, which is the fastest.
+    #[default]
+    Arbitrary,
+    /// Selects the ties with the smallest ids, so the results are reproducible.
+    /// The results are sorted by distances and then by ids.
+    SmallestId,
+    /// Includes all the ties, so more than K results can be returned.
+    /// The results are sorted by distances and then by ids.
+    IncludeAll,
 }
 
 /// Range searcher created by [`Index::range_searcher()`].
@@ -72,6 +121,7 @@ pub struct RangeSearcher<'a, T: CodeInt> {
     index: &'a Index<T>,
     siggen: siggen::SigGenerator64,
     answers: Vec<u32>,
+    neighbors: Vec<Neighbor>,
 }
 
 /// Top-K searcher created by [`Index::range_searcher()`].
@@ -79,5 +129,25 @@ pub struct TopkSearcher<'a, T: CodeInt> {
     index: &'a Index<T>,
     siggen: siggen::SigGenerator64,
     answers: Vec<u32>,
+    neighbors: Vec<Neighbor>,
     checked: std::collections::HashSet<usize>,
+    tie_policy: TiePolicy,
+}
+
+/// Iterator over neighbors in nondecreasing Hamming distances, created by [`Index::nearest_neighbors()`].
+pub struct NeighborIter<'a, T: CodeInt> {
+    index: &'a Index<T>,
+    qcode: T,
+    siggen: siggen::SigGenerator64,
+    checked: std::collections::HashSet<u32>,
+    // Found ids for each distance.
+    buckets: Vec<Vec<u32>>,
+    // The next block and radius to be probed.
+    block: usize,
+    radius: usize,
+    // All the neighbors with distances less than this have been found.
+    complete: usize,
+    // The next neighbor to be yielded.
+    dist: usize,
+    pos: usize,
 }
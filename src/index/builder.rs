@@ -0,0 +1,865 @@
+use anyhow::{anyhow, Result};
+
+use crate::codeint::extract_words;
+use crate::index::sparsehash;
+use crate::{CodeInt, Index};
+
+/// The maximum number of codes sampled to estimate bit correlations.
+const CORRELATION_SAMPLE_SIZE: usize = 1 << 12;
+
+/// The maximum number of codes sampled to estimate bucket occupancy.
+const COST_SAMPLE_SIZE: usize = 1 << 7;
+
+/// The number of bits by which a block chosen by the cost model can exceed
+/// both the even width and the logarithm of the number of codes.
+const COST_WIDTH_SLACK: usize = 4;
+
+/// Bit permutation applied before partitioning binary codes into blocks.
+///
+/// Multi-index hashing assumes that the bits in each block are independent.
+/// If neighboring bits are correlated, the contiguous blocks have skewed tables,
+/// and permuting the bits can decorrelate them.
+/// The permutation only changes how bits are grouped into blocks,
+/// so the stored codes, query codes, and search results are not affected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BitPermutation {
+    /// No permutation, i.e., each block consists of contiguous bits.
+    #[default]
+    Identity,
+    /// Random permutation generated from the given seed.
+    Random(u64),
+    /// Permutation that greedily assigns strongly correlated bits to different blocks,
+    /// estimated from a sample of the input codes.
+    Correlation,
+}
+
+/// Strategy to decide the number of bits in each block.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BlockWidths {
+    /// Every block has `(b + num_dimensions) / num_blocks` bits.
+    #[default]
+    Even,
+    /// Widths minimizing the expected cost of a range search with the given radius,
+    /// i.e., the number of probed signatures plus the number of candidates to be verified.
+    ///
+    /// The occupancy of buckets is estimated from the distances between pairs of sampled codes.
+    /// Blocks with skewed bits become wider to reduce collisions,
+    /// and blocks with uniform bits become narrower to save memory.
+    /// Since the layout is tuned for the given radius, searches with
+    /// a much different radius may be slower than with [`BlockWidths::Even`].
+    CostModel {
+        /// The radius of range searches expected to be run.
+        radius: usize,
+    },
+}
+
+/// Builder of [`Index`] with optional parameters.
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::{BitPermutation, IndexBuilder};
+///
+/// // 32-bit codes where each bit is duplicated into two neighboring bits
+/// let codes: Vec<u64> = vec![0x0000_0000_0000_000F, 0x0000_0000_0000_00FF, 0xFFFF_0000_0000_0000];
+///
+/// let index = IndexBuilder::new()
+///     .num_blocks(4)
+///     .permutation(BitPermutation::Correlation)
+///     .build(codes)
+///     .unwrap();
+///
+/// let mut searcher = index.range_searcher();
+/// assert_eq!(searcher.run(0x0000_0000_0000_000F, 4), vec![0, 1]);
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct IndexBuilder {
+    num_dimensions: Option<usize>,
+    num_blocks: Option<usize>,
+    permutation: BitPermutation,
+    block_widths: BlockWidths,
+    partition: Option<Vec<Vec<usize>>>,
+    num_excluded_bits: usize,
+}
+
+impl IndexBuilder {
+    /// Creates a builder with the default parameters.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of meaningful bits in a binary code (see [`Index::with_dimensions()`]).
+    /// [`CodeInt::dimensions()`] is used by default.
+    pub const fn num_dimensions(mut self, num_dimensions: usize) -> Self {
+        self.num_dimensions = Some(num_dimensions);
+        self
+    }
+
+    /// Sets the number of blocks for multi-index.
+    /// The optimal one estimated from the number of input codes is used by default.
+    pub const fn num_blocks(mut self, num_blocks: usize) -> Self {
+        self.num_blocks = Some(num_blocks);
+        self
+    }
+
+    /// Sets the bit permutation applied before partitioning codes into blocks.
+    /// [`BitPermutation::Identity`] is used by default.
+    pub const fn permutation(mut self, permutation: BitPermutation) -> Self {
+        self.permutation = permutation;
+        self
+    }
+
+    /// Sets the strategy to decide the number of bits in each block.
+    /// [`BlockWidths::Even`] is used by default.
+    /// The resulting layout can be gotten with [`Index::block()`].
+    ///
+    /// If a permutation is also set, the widths are decided on the permuted bits.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::{BlockWidths, IndexBuilder};
+    ///
+    /// // 32-bit codes whose lowest 16 bits are always zero
+    /// let codes: Vec<u32> = (0..1000u32).map(|x| x.wrapping_mul(0x9E37_0000)).collect();
+    ///
+    /// let index = IndexBuilder::new()
+    ///     .num_blocks(4)
+    ///     .block_widths(BlockWidths::CostModel { radius: 3 })
+    ///     .build(codes)
+    ///     .unwrap();
+    /// let widths: Vec<usize> = (0..4).map(|b| index.block(b).len()).collect();
+    /// assert_eq!(widths.iter().sum::<usize>(), 32);
+    /// ```
+    pub const fn block_widths(mut self, block_widths: BlockWidths) -> Self {
+        self.block_widths = block_widths;
+        self
+    }
+
+    /// Sets an explicit partition of bits into blocks, where `partition[b]` lists
+    /// the bit positions of the `b`-th block.
+    /// The blocks need not consist of contiguous bits,
+    /// but each bit in `[0, num_dimensions)` must belong to exactly one block.
+    /// If this is set, [`IndexBuilder::num_blocks()`], [`IndexBuilder::permutation()`],
+    /// and [`IndexBuilder::block_widths()`] cannot be set to a conflicting value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::IndexBuilder;
+    ///
+    /// // 16-bit codes concatenating two 8-bit sub-descriptors
+    /// let codes: Vec<u16> = vec![0x00FF, 0x0F0F, 0xFF00];
+    ///
+    /// // Each block takes a half of each sub-descriptor
+    /// let index = IndexBuilder::new()
+    ///     .partition(vec![
+    ///         vec![0, 1, 2, 3, 8, 9, 10, 11],
+    ///         vec![4, 5, 6, 7, 12, 13, 14, 15],
+    ///     ])
+    ///     .build(codes)
+    ///     .unwrap();
+    /// assert_eq!(index.block(0), &[0, 1, 2, 3, 8, 9, 10, 11]);
+    ///
+    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(searcher.run(0x00FF, 8), vec![0, 1]);
+    /// ```
+    pub fn partition(mut self, partition: Vec<Vec<usize>>) -> Self {
+        self.partition = Some(partition);
+        self
+    }
+
+    /// Excludes the lowest `num_bits` bits from the blocks, e.g., the bits shared by all the codes.
+    /// The excluded bits are dropped from the partition if it is set,
+    /// but they are still counted in the distances to be verified.
+    pub(crate) const fn exclude_low_bits(mut self, num_bits: usize) -> Self {
+        self.num_excluded_bits = num_bits;
+        self
+    }
+
+    /// Builds an index from binary codes.
+    /// The input database `codes` is stolen, but the reference can be gotten with [`Index::codes()`].
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the `codes` is empty,
+    ///  - the number of entries in `codes` is more than `u32::MAX`,
+    ///  - the number of dimensions is zero or more than [`CodeInt::dimensions()`],
+    ///  - a code has a bit set at or above the number of dimensions,
+    ///  - the number of blocks is less than 2 or more than the number of dimensions,
+    ///  - a block would be wider than 32 bits,
+    ///  - the partition does not assign each bit to exactly one block, or
+    ///  - the partition conflicts with the other options.
+    pub fn build<T: CodeInt>(&self, codes: Vec<T>) -> Result<Index<T>> {
+        let num_dimensions = self.num_dimensions.unwrap_or_else(T::dimensions);
+        if num_dimensions == 0 || T::dimensions() < num_dimensions {
+            return Err(anyhow!(
+                "The number of dimensions {} must be in [1,{}]",
+                num_dimensions,
+                T::dimensions()
+            ));
+        }
+
+        if let Some(partition) = &self.partition {
+            let (bits, begs) = self.check_partition(partition, num_dimensions)?;
+            return Index::from_layout(codes, num_dimensions, bits, begs);
+        }
+
+        // The bits in [first_bit, num_dimensions) are partitioned into blocks.
+        let first_bit = self.num_excluded_bits.min(num_dimensions);
+        let num_indexed = num_dimensions - first_bit;
+        let num_blocks = self
+            .num_blocks
+            .unwrap_or_else(|| optimal_blocks(codes.len(), num_indexed));
+        if num_blocks < 2 || num_indexed < num_blocks {
+            return Err(anyhow!(
+                "The number of blocks {} must not be in [2,{}]",
+                num_blocks,
+                num_indexed
+            ));
+        }
+
+        let mut begs = vec![0; num_blocks + 1];
+        for b in 0..num_blocks {
+            let dim = (b + num_indexed) / num_blocks;
+            begs[b + 1] = begs[b] + dim;
+        }
+
+        let mut bits: Vec<usize> = match self.permutation {
+            BitPermutation::Identity => (first_bit..num_dimensions).collect(),
+            BitPermutation::Random(seed) => random_permutation(num_indexed, seed)
+                .into_iter()
+                .map(|bit| bit + first_bit)
+                .collect(),
+            BitPermutation::Correlation => {
+                correlation_permutation(&codes, first_bit, num_dimensions, &begs)
+            }
+        };
+        if let BlockWidths::CostModel { radius } = self.block_widths {
+            begs = cost_model_begs(&codes, &bits, num_blocks, radius);
+        }
+        for b in 0..num_blocks {
+            bits[begs[b]..begs[b + 1]].sort_unstable();
+        }
+
+        Index::from_layout(codes, num_dimensions, bits, begs)
+    }
+
+    /// Validates the partition and converts it into the layout of bits.
+    fn check_partition(
+        &self,
+        partition: &[Vec<usize>],
+        num_dimensions: usize,
+    ) -> Result<(Vec<usize>, Vec<usize>)> {
+        let num_blocks = partition.len();
+        if num_blocks < 2 || num_dimensions < num_blocks {
+            return Err(anyhow!(
+                "The number of blocks {} must not be in [2,{}]",
+                num_blocks,
+                num_dimensions
+            ));
+        }
+        if self.num_blocks.is_some_and(|n| n != num_blocks) {
+            return Err(anyhow!(
+                "The number of blocks {} must be equal to that of the partition {}",
+                self.num_blocks.unwrap(),
+                num_blocks
+            ));
+        }
+        if self.permutation != BitPermutation::Identity {
+            return Err(anyhow!(
+                "The permutation must not be specified with the partition"
+            ));
+        }
+        if self.block_widths != BlockWidths::Even {
+            return Err(anyhow!(
+                "The block widths must not be specified with the partition"
+            ));
+        }
+
+        // The excluded bits are regarded as already seen.
+        let first_bit = self.num_excluded_bits.min(num_dimensions);
+        let mut seen = vec![false; num_dimensions];
+        seen[..first_bit].fill(true);
+        let mut bits = Vec::with_capacity(num_dimensions);
+        let mut begs = vec![0];
+        for (b, block) in partition.iter().enumerate() {
+            let mut block: Vec<usize> = block
+                .iter()
+                .copied()
+                .filter(|&bit| first_bit <= bit)
+                .collect();
+            if block.is_empty() || sparsehash::MAX_BITS < block.len() {
+                return Err(anyhow!(
+                    "The number of bits {} in the block {} must be in [1,{}]",
+                    block.len(),
+                    b,
+                    sparsehash::MAX_BITS
+                ));
+            }
+            block.sort_unstable();
+            for &bit in &block {
+                if num_dimensions <= bit {
+                    return Err(anyhow!(
+                        "The bit {} in the block {} must be less than {}",
+                        bit,
+                        b,
+                        num_dimensions
+                    ));
+                }
+                if seen[bit] {
+                    return Err(anyhow!("The bit {} must not be in multiple blocks", bit));
+                }
+                seen[bit] = true;
+            }
+            bits.extend(block);
+            begs.push(bits.len());
+        }
+        if let Some(bit) = seen.iter().position(|&x| !x) {
+            return Err(anyhow!("The bit {} must be in a block", bit));
+        }
+        Ok((bits, begs))
+    }
+}
+
+/// Estimates the optimal number of blocks from the number of codes.
+fn optimal_blocks(num_codes: usize, num_dimensions: usize) -> usize {
+    let num_codes = num_codes as f64;
+    let dimensions = num_dimensions as f64;
+
+    let blocks = (dimensions / num_codes.log2()).round() as usize;
+    blocks.min(num_dimensions).max(2)
+}
+
+/// Generates a random permutation of `[0, n)` with the Fisher-Yates shuffle.
+fn random_permutation(n: usize, seed: u64) -> Vec<usize> {
+    let mut state = seed;
+    let mut perm: Vec<usize> = (0..n).collect();
+    for i in (1..n).rev() {
+        let j = (splitmix64(&mut state) % (i as u64 + 1)) as usize;
+        perm.swap(i, j);
+    }
+    perm
+}
+
+const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Generates a permutation that assigns strongly correlated bits to different blocks.
+///
+/// The absolute correlation of each pair of bits is estimated from a sample of codes.
+/// The next bit to be visited is the one most correlated to the bits already assigned
+/// (i.e., the most constrained one), and it is assigned to the block with free space
+/// whose bits are the least correlated to it.
+/// Only the bits in `[first_bit, num_dimensions)` are assigned.
+fn correlation_permutation<T: CodeInt>(
+    codes: &[T],
+    first_bit: usize,
+    num_dimensions: usize,
+    begs: &[usize],
+) -> Vec<usize> {
+    let num_blocks = begs.len() - 1;
+    let corrs: Vec<Vec<f64>> = bit_correlations(codes, num_dimensions)
+        .into_iter()
+        .skip(first_bit)
+        .map(|row| row[first_bit..].to_vec())
+        .collect();
+    let num_dimensions = num_dimensions - first_bit;
+
+    let totals: Vec<f64> = corrs.iter().map(|row| row.iter().sum()).collect();
+
+    // costs[b][i] is the total correlation between the bit i and the bits in the block b.
+    let mut costs = vec![vec![0f64; num_dimensions]; num_blocks];
+    let mut blocks = vec![Vec::new(); num_blocks];
+    let mut assigned = vec![false; num_dimensions];
+
+    for _ in 0..num_dimensions {
+        // The affinity of a bit is its total correlation to the assigned bits.
+        let affinity = |k: usize| costs.iter().map(|c| c[k]).sum::<f64>();
+        let i = (0..num_dimensions)
+            .filter(|&k| !assigned[k])
+            .max_by(|&j, &k| {
+                (affinity(j), totals[j])
+                    .partial_cmp(&(affinity(k), totals[k]))
+                    .unwrap()
+            })
+            .unwrap();
+        assigned[i] = true;
+
+        // Prefers the least correlated block, and then the block with the most free space.
+        let mut best: Option<(usize, usize)> = None;
+        for b in 0..num_blocks {
+            let space = begs[b + 1] - begs[b] - blocks[b].len();
+            if space == 0 {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((c, s)) => {
+                    costs[b][i] < costs[c][i] || (costs[b][i] == costs[c][i] && s < space)
+                }
+            };
+            if better {
+                best = Some((b, space));
+            }
+        }
+        let (b, _) = best.unwrap();
+        blocks[b].push(i);
+        for (cost, &corr) in costs[b].iter_mut().zip(corrs[i].iter()) {
+            *cost += corr;
+        }
+    }
+
+    let mut bits = blocks.concat();
+    bits.iter_mut().for_each(|bit| *bit += first_bit);
+    bits
+}
+
+/// Decides the block widths on `bits` that minimize the expected cost of a range search.
+///
+/// The cost of the `b`-th block is the number of signatures probed with the radius of
+/// the pigeonhole principle plus the expected number of codes found in the buckets,
+/// which is estimated from the ratio of sampled pairs whose distances in the block are within the radius.
+/// Since the cost is additive, the optimal widths are found by dynamic programming.
+fn cost_model_begs<T: CodeInt>(
+    codes: &[T],
+    bits: &[usize],
+    num_blocks: usize,
+    radius: usize,
+) -> Vec<usize> {
+    let num_dimensions = bits.len();
+    let log_codes = (usize::BITS - codes.len().leading_zeros()) as usize;
+    let max_width = (num_dimensions.div_ceil(num_blocks).max(log_codes) + COST_WIDTH_SLACK)
+        .min(sparsehash::MAX_BITS)
+        .min(num_dimensions - num_blocks + 1)
+        .max(num_dimensions.div_ceil(num_blocks));
+    let max_rad = radius / num_blocks;
+
+    // Sampled codes whose i-th bit is the bits[i]-th bit of the original code.
+    let step = (codes.len() / COST_SAMPLE_SIZE).max(1);
+    let num_words = num_dimensions.div_ceil(64);
+    let sample: Vec<Vec<u64>> = codes
+        .iter()
+        .step_by(step)
+        .map(|code| {
+            let mut words = vec![0u64; num_words];
+            for (i, &bit) in bits.iter().enumerate() {
+                words[i / 64] |= code.extract(bit, bit + 1) << (i % 64);
+            }
+            words
+        })
+        .collect();
+
+    let mut xors = Vec::new();
+    for i in 0..sample.len() {
+        for j in i + 1..sample.len() {
+            xors.push(
+                sample[i]
+                    .iter()
+                    .zip(sample[j].iter())
+                    .map(|(x, y)| x ^ y)
+                    .collect::<Vec<_>>(),
+            );
+        }
+    }
+    let num_pairs = xors.len().max(1) as f64;
+
+    // costs[b][beg][w - 1] is the cost of the b-th block consisting of [beg, beg + w).
+    let mut within = vec![0usize; max_rad + 1];
+    let mut costs = vec![vec![vec![0f64; max_width]; num_dimensions]; num_blocks];
+    for beg in 0..num_dimensions {
+        for w in 1..=max_width.min(num_dimensions - beg) {
+            within.iter_mut().for_each(|x| *x = 0);
+            for xor in &xors {
+                let dist = extract_words(xor, beg, beg + w).count_ones() as usize;
+                if dist <= max_rad {
+                    within[dist] += 1;
+                }
+            }
+            for r in 1..=max_rad {
+                within[r] += within[r - 1];
+            }
+
+            for (b, block_costs) in costs.iter_mut().enumerate() {
+                if b + radius + 1 < num_blocks {
+                    continue;
+                }
+                let rad = (b + radius + 1 - num_blocks) / num_blocks;
+                let mut probes = 0f64;
+                let mut binom = 1f64;
+                for r in 0..=rad.min(w) {
+                    probes += binom;
+                    binom = binom * (w - r) as f64 / (r + 1) as f64;
+                }
+                let candidates = codes.len() as f64 * within[rad] as f64 / num_pairs;
+                block_costs[beg][w - 1] = probes + candidates;
+            }
+        }
+    }
+
+    // dp[b][pos] is the minimum cost of the first b blocks consisting of [0, pos).
+    let mut dp = vec![vec![(f64::INFINITY, 0); num_dimensions + 1]; num_blocks + 1];
+    dp[0][0] = (0f64, 0);
+    for b in 0..num_blocks {
+        for pos in 0..num_dimensions {
+            let (cost, _) = dp[b][pos];
+            if cost == f64::INFINITY {
+                continue;
+            }
+            for w in 1..=max_width.min(num_dimensions - pos) {
+                let next = cost + costs[b][pos][w - 1];
+                if next < dp[b + 1][pos + w].0 {
+                    dp[b + 1][pos + w] = (next, pos);
+                }
+            }
+        }
+    }
+
+    let mut begs = vec![num_dimensions; num_blocks + 1];
+    for b in (0..num_blocks).rev() {
+        begs[b] = dp[b + 1][begs[b + 1]].1;
+    }
+    begs
+}
+
+/// Estimates the absolute correlation (phi coefficient) of each pair of bits.
+/// Correlations that are not significant for the sample size are regarded as zero,
+/// so that sampling noise does not drive the assignment.
+fn bit_correlations<T: CodeInt>(codes: &[T], num_dimensions: usize) -> Vec<Vec<f64>> {
+    let step = (codes.len() / CORRELATION_SAMPLE_SIZE).max(1);
+    let sample: Vec<&T> = codes.iter().step_by(step).collect();
+    let n = sample.len();
+
+    // columns[i] is the bitmap of the codes in which the bit i is set.
+    let num_words = n.div_ceil(64);
+    let mut columns = vec![vec![0u64; num_words]; num_dimensions];
+    for (j, code) in sample.iter().enumerate() {
+        let mut beg = 0;
+        while beg < num_dimensions {
+            let end = num_dimensions.min(beg + 64);
+            let mut x = code.extract(beg, end);
+            while x != 0 {
+                let i = beg + x.trailing_zeros() as usize;
+                columns[i][j / 64] |= 1 << (j % 64);
+                x &= x - 1;
+            }
+            beg = end;
+        }
+    }
+
+    let ones: Vec<f64> = columns
+        .iter()
+        .map(|col| col.iter().map(|x| x.count_ones()).sum::<u32>() as f64)
+        .collect();
+
+    let n = n as f64;
+    let threshold = 4.0 / n.sqrt();
+    let mut corrs = vec![vec![0.0; num_dimensions]; num_dimensions];
+    for i in 0..num_dimensions {
+        for j in i + 1..num_dimensions {
+            let both = columns[i]
+                .iter()
+                .zip(columns[j].iter())
+                .map(|(x, y)| (x & y).count_ones())
+                .sum::<u32>() as f64;
+            let denom = (ones[i] * (n - ones[i]) * ones[j] * (n - ones[j])).sqrt();
+            if denom > 0.0 {
+                let corr = ((n * both - ones[i] * ones[j]) / denom).abs();
+                if corr >= threshold {
+                    corrs[i][j] = corr;
+                    corrs[j][i] = corr;
+                }
+            }
+        }
+    }
+    corrs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ls;
+
+    use rand::{thread_rng, Rng};
+
+    /// Generates 64-bit codes in which the bits 2i and 2i+1 are always equal.
+    fn gen_correlated_codes(size: usize) -> Vec<u64> {
+        let mut rng = thread_rng();
+        (0..size)
+            .map(|_| {
+                let x: u32 = rng.gen();
+                (0..32).fold(0, |c, i| c | (((x as u64 >> i) & 1) * 3) << (2 * i))
+            })
+            .collect()
+    }
+
+    fn block_of(index: &Index<u64>, bit: usize) -> usize {
+        let pos = index.bits.iter().position(|&x| x == bit).unwrap();
+        (0..index.num_blocks())
+            .find(|&b| index.begs[b] <= pos && pos < index.begs[b + 1])
+            .unwrap()
+    }
+
+    #[test]
+    fn random_permutation_works() {
+        let perm = random_permutation(100, 42);
+        assert_eq!(perm, random_permutation(100, 42));
+        assert_ne!(perm, (0..100).collect::<Vec<_>>());
+
+        let mut sorted = perm.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn correlation_permutation_works() {
+        let codes = gen_correlated_codes(10000);
+        let index = IndexBuilder::new()
+            .permutation(BitPermutation::Correlation)
+            .build(codes)
+            .unwrap();
+
+        for i in 0..32 {
+            assert_ne!(block_of(&index, 2 * i), block_of(&index, 2 * i + 1));
+        }
+    }
+
+    #[test]
+    fn correlation_permutation_reduces_collisions() {
+        let collisions = |index: &Index<u64>| -> usize {
+            let mut sum = 0;
+            for table in &index.tables {
+                for idx in 0..table.len() {
+                    sum += table.array_len(idx) * table.array_len(idx);
+                }
+            }
+            sum
+        };
+
+        let codes = gen_correlated_codes(10000);
+        let index1 = IndexBuilder::new().build(codes.clone()).unwrap();
+        let index2 = IndexBuilder::new()
+            .permutation(BitPermutation::Correlation)
+            .build(codes)
+            .unwrap();
+        assert!(collisions(&index2) < collisions(&index1));
+    }
+
+    #[test]
+    fn permuted_search_works() {
+        let codes = gen_correlated_codes(10000);
+        for &permutation in &[BitPermutation::Random(13), BitPermutation::Correlation] {
+            let index = IndexBuilder::new()
+                .permutation(permutation)
+                .build(codes.clone())
+                .unwrap();
+            assert_eq!(index.codes(), &codes[..]);
+
+            let mut range_searcher = index.range_searcher();
+            let mut topk_searcher = index.topk_searcher();
+            for qi in (0..10000).step_by(100) {
+                let qcode = codes[qi];
+                for rad in 0..6 {
+                    let ans1 = ls::range_search(&codes, qcode, rad);
+                    let ans2 = range_searcher.run(qcode, rad);
+                    assert_eq!(ans1, ans2);
+                }
+
+                let mut dists = ls::exhaustive_search(&codes, qcode);
+                dists.sort_by_key(|x| x.1);
+                let ans = topk_searcher.run(qcode, 10);
+                for (i, &id) in ans.iter().enumerate() {
+                    assert_eq!(
+                        crate::hamdist(qcode, codes[id as usize]),
+                        dists[i].1 as usize
+                    );
+                }
+            }
+
+            let mut data = vec![];
+            index.serialize_into(&mut data).unwrap();
+            let other = Index::<u64>::deserialize_from(&data[..]).unwrap();
+            assert_eq!(index, other);
+        }
+    }
+
+    /// Generates 64-bit codes whose lower half is sparse and upper half is uniform.
+    fn gen_skewed_codes(size: usize) -> Vec<u64> {
+        let mut rng = thread_rng();
+        (0..size)
+            .map(|_| {
+                let lo = (0..32).fold(0u64, |c, i| c | ((rng.gen_range(0..16) == 0) as u64) << i);
+                let hi: u32 = rng.gen();
+                lo | (hi as u64) << 32
+            })
+            .collect()
+    }
+
+    /// Counts the codes found in the buckets probed by a range search.
+    fn num_candidates(index: &Index<u64>, qcode: u64, radius: usize) -> usize {
+        let num_blocks = index.num_blocks();
+        let mut siggen = crate::index::siggen::SigGenerator64::new();
+        let mut n = 0;
+        for b in 0..num_blocks {
+            if b + radius + 1 < num_blocks {
+                continue;
+            }
+            let rad = (b + radius + 1 - num_blocks) / num_blocks;
+            let qcd = index.get_chunk(&qcode, b);
+            for r in 0..rad + 1 {
+                siggen.init(qcd, index.get_dim(b), r);
+                while siggen.has_next() {
+                    n += index.tables[b].array_len(siggen.next() as usize);
+                }
+            }
+        }
+        n
+    }
+
+    #[test]
+    fn cost_model_works() {
+        let codes = gen_skewed_codes(10000);
+        let radius = 7;
+
+        let index1 = IndexBuilder::new()
+            .num_blocks(4)
+            .build(codes.clone())
+            .unwrap();
+        let index2 = IndexBuilder::new()
+            .num_blocks(4)
+            .block_widths(BlockWidths::CostModel { radius })
+            .build(codes.clone())
+            .unwrap();
+
+        let widths: Vec<usize> = (0..4).map(|b| index2.block(b).len()).collect();
+        assert_eq!(widths.iter().sum::<usize>(), 64);
+        assert!(widths.iter().all(|&w| 0 < w && w <= 16 + COST_WIDTH_SLACK));
+        // The sparse lower half needs more bits
+        assert!(index2.block(0).len() > 16);
+        assert!(index2.block(3).len() < 16);
+
+        let mut cands1 = 0;
+        let mut cands2 = 0;
+        let mut searcher = index2.range_searcher();
+        for qi in (0..10000).step_by(100) {
+            let qcode = codes[qi];
+            cands1 += num_candidates(&index1, qcode, radius);
+            cands2 += num_candidates(&index2, qcode, radius);
+            for rad in [0, 3, radius, 10] {
+                assert_eq!(
+                    ls::range_search(&codes, qcode, rad),
+                    searcher.run(qcode, rad)
+                );
+            }
+        }
+        assert!(cands2 < cands1);
+
+        let mut data = vec![];
+        index2.serialize_into(&mut data).unwrap();
+        let other = Index::<u64>::deserialize_from(&data[..]).unwrap();
+        assert_eq!(index2, other);
+    }
+
+    #[test]
+    fn partition_works() {
+        let codes = gen_correlated_codes(10000);
+
+        // Interleaved blocks of bits b, b+5, b+10, ...
+        let partition: Vec<Vec<usize>> =
+            (0..5).map(|b| (b..64).step_by(5).rev().collect()).collect();
+        let index = IndexBuilder::new()
+            .partition(partition.clone())
+            .build(codes.clone())
+            .unwrap();
+        assert_eq!(index.num_blocks(), 5);
+        for (b, block) in partition.iter().enumerate() {
+            let mut block = block.clone();
+            block.sort_unstable();
+            assert_eq!(index.block(b), &block[..]);
+        }
+
+        let mut range_searcher = index.range_searcher();
+        let mut topk_searcher = index.topk_searcher();
+        for qi in (0..10000).step_by(100) {
+            let qcode = codes[qi];
+            for rad in 0..8 {
+                let ans1 = ls::range_search(&codes, qcode, rad);
+                let ans2 = range_searcher.run(qcode, rad);
+                assert_eq!(ans1, ans2);
+            }
+
+            let mut dists = ls::exhaustive_search(&codes, qcode);
+            dists.sort_by_key(|x| x.1);
+            let ans = topk_searcher.run(qcode, 10);
+            for (i, &id) in ans.iter().enumerate() {
+                assert_eq!(
+                    crate::hamdist(qcode, codes[id as usize]),
+                    dists[i].1 as usize
+                );
+            }
+        }
+
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+        let other = Index::<u64>::deserialize_from(&data[..]).unwrap();
+        assert_eq!(index, other);
+        assert_eq!(other.block(3), index.block(3));
+    }
+
+    #[test]
+    fn partition_fails() {
+        let codes: Vec<u16> = vec![0x00FF, 0x0F0F, 0xFF00];
+        let build = |builder: IndexBuilder| builder.build(codes.clone());
+        let halves = || vec![(0..8).collect(), (8..16).collect::<Vec<_>>()];
+
+        assert!(build(IndexBuilder::new().partition(halves())).is_ok());
+        // Single block
+        assert!(build(IndexBuilder::new().partition(vec![(0..16).collect()])).is_err());
+        // Empty block
+        assert!(build(IndexBuilder::new().partition(vec![(0..16).collect(), vec![]])).is_err());
+        // Uncovered bit
+        assert!(
+            build(IndexBuilder::new().partition(vec![(0..8).collect(), (8..15).collect()]))
+                .is_err()
+        );
+        // Duplicated bit
+        assert!(
+            build(IndexBuilder::new().partition(vec![(0..9).collect(), (8..16).collect()]))
+                .is_err()
+        );
+        // Out-of-range bit
+        assert!(
+            build(IndexBuilder::new().partition(vec![(0..8).collect(), (8..17).collect()]))
+                .is_err()
+        );
+        // Conflicting options
+        assert!(build(IndexBuilder::new().num_blocks(3).partition(halves())).is_err());
+        assert!(build(
+            IndexBuilder::new()
+                .block_widths(BlockWidths::CostModel { radius: 2 })
+                .partition(halves())
+        )
+        .is_err());
+        assert!(build(IndexBuilder::new().num_blocks(2).partition(halves())).is_ok());
+        assert!(build(
+            IndexBuilder::new()
+                .permutation(BitPermutation::Random(1))
+                .partition(halves())
+        )
+        .is_err());
+        // Bits beyond the dimensions
+        assert!(build(IndexBuilder::new().num_dimensions(12).partition(halves())).is_err());
+        assert!(IndexBuilder::new()
+            .num_dimensions(12)
+            .partition(vec![(0..6).collect(), (6..12).collect()])
+            .build(vec![0x00FFu16, 0x0F0F])
+            .is_ok());
+    }
+}
@@ -2,7 +2,13 @@ use anyhow::{anyhow, Result};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use crate::{hamdist, index::*, Index};
+use crate::{index::*, DynCode, Index, WideCode};
+
+/// Magic number at the beginning of a serialized index.
+const MAGIC: &[u8; 8] = b"MIHRSIDX";
+
+/// Version of the serialization format, incremented whenever the layout changes.
+const FORMAT_VERSION: u64 = 1;
 
 impl<T: CodeInt> Index<T> {
     /// Builds an index from binary codes.
@@ -19,17 +25,9 @@ impl<T: CodeInt> Index<T> {
     /// `anyhow::Error` will be returned when
     ///
     ///  - the `codes` is empty, or
-    ///  - the number of entries in `codes` is more than `u32::max_value()`.
+    ///  - the number of entries in `codes` is more than `u32::MAX`.
     pub fn new(codes: Vec<T>) -> Result<Self> {
-        let num_codes = codes.len() as f64;
-        let dimensions = T::dimensions() as f64;
-
-        let blocks = (dimensions / num_codes.log2()).round() as usize;
-        if blocks < 2 {
-            Self::with_blocks(codes, 2)
-        } else {
-            Self::with_blocks(codes, blocks)
-        }
+        Self::with_dimensions(codes, T::dimensions())
     }
 
     /// Builds an index from binary codes with a manually specified number of blocks.
@@ -45,71 +43,157 @@ impl<T: CodeInt> Index<T> {
     /// `anyhow::Error` will be returned when
     ///
     ///  - the `codes` is empty,
-    ///  - the number of entries in `codes` is more than `u32::max_value()`, or
-    ///  - `num_blocks` is less than 2 or more than the number of dimensions in a binary code.
+    ///  - the number of entries in `codes` is more than `u32::MAX`,
+    ///  - `num_blocks` is less than 2 or more than the number of dimensions in a binary code, or
+    ///  - a block would be wider than 32 bits (e.g., `num_blocks` is less than 4 for [`u128`]).
     pub fn with_blocks(codes: Vec<T>, num_blocks: usize) -> Result<Self> {
+        Self::with_dimensions_and_blocks(codes, T::dimensions(), num_blocks)
+    }
+
+    /// Builds an index from binary codes whose meaningful bits are only the lowest `num_dimensions` bits.
+    /// The number of blocks for multi-index is set to the optimal one
+    /// estimated from the number of input codes.
+    /// The input database `codes` is stolen, but the reference can be gotten with [`Index::codes()`].
+    ///
+    /// The bits at or above `num_dimensions` must be zero in both the input codes and query codes.
+    /// Searches with a query code that has such a bit find no neighbors.
+    ///
+    /// # Arguments
+    ///
+    /// - `codes`: Vector of binary codes of type [`CodeInt`].
+    /// - `num_dimensions`: The number of meaningful bits in a binary code.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the `codes` is empty,
+    ///  - the number of entries in `codes` is more than `u32::MAX`,
+    ///  - `num_dimensions` is zero or more than [`CodeInt::dimensions()`], or
+    ///  - a code has a bit set at or above `num_dimensions`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// // 48-bit perceptual hashes stored in u64
+    /// let codes: Vec<u64> = vec![
+    ///     0x0000_FFFF_FFFF_FFFE, // #zeros = 1
+    ///     0x0000_FFFF_0000_FFFF, // #zeros = 16
+    ///     0x0000_FFFF_FFFF_FFFF, // #zeros = 0
+    /// ];
+    ///
+    /// let index = Index::with_dimensions(codes, 48).unwrap();
+    /// assert_eq!(index.num_dimensions(), 48);
+    ///
+    /// let mut searcher = index.topk_searcher();
+    /// let answers = searcher.run(0x0000_FFFF_FFFF_FFFF, 2);
+    /// assert_eq!(answers, vec![2, 0]);
+    /// ```
+    pub fn with_dimensions(codes: Vec<T>, num_dimensions: usize) -> Result<Self> {
+        IndexBuilder::new()
+            .num_dimensions(num_dimensions)
+            .build(codes)
+    }
+
+    /// Builds an index from binary codes whose meaningful bits are only the lowest `num_dimensions` bits,
+    /// with a manually specified number of blocks.
+    /// The input database `codes` is stolen, but the reference can be gotten with [`Index::codes()`].
+    ///
+    /// The bits at or above `num_dimensions` must be zero in both the input codes and query codes.
+    /// Searches with a query code that has such a bit find no neighbors.
+    ///
+    /// # Arguments
+    ///
+    /// - `codes`: Vector of binary codes of type [`CodeInt`].
+    /// - `num_dimensions`: The number of meaningful bits in a binary code.
+    /// - `num_blocks`: The number of blocks for multi-index.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the `codes` is empty,
+    ///  - the number of entries in `codes` is more than `u32::MAX`,
+    ///  - `num_dimensions` is zero or more than [`CodeInt::dimensions()`],
+    ///  - a code has a bit set at or above `num_dimensions`,
+    ///  - `num_blocks` is less than 2 or more than `num_dimensions`, or
+    ///  - a block would be wider than 32 bits.
+    pub fn with_dimensions_and_blocks(
+        codes: Vec<T>,
+        num_dimensions: usize,
+        num_blocks: usize,
+    ) -> Result<Self> {
+        IndexBuilder::new()
+            .num_dimensions(num_dimensions)
+            .num_blocks(num_blocks)
+            .build(codes)
+    }
+
+    /// Builds an index whose `b`-th block consists of the bits `bits[begs[b]..begs[b + 1]]`.
+    pub(super) fn from_layout(
+        codes: Vec<T>,
+        num_dimensions: usize,
+        bits: Vec<usize>,
+        begs: Vec<usize>,
+    ) -> Result<Self> {
         if codes.is_empty() {
             return Err(anyhow!("The input codes must not be empty"));
         }
 
-        if (u32::max_value() as usize) < codes.len() {
+        if (u32::MAX as usize) < codes.len() {
             return Err(anyhow!(
                 "The number of codes {} must not be no more than {}.",
                 codes.len(),
-                u32::max_value()
+                u32::MAX
             ));
         }
 
-        let num_dimensions = T::dimensions();
-        if num_blocks < 2 || num_dimensions < num_blocks {
+        if let Some(id) = codes
+            .iter()
+            .position(|code| has_high_bits(code, num_dimensions))
+        {
             return Err(anyhow!(
-                "The number of blocks {} must not be in [2,{}]",
-                num_blocks,
+                "The code {} must not have bits at or above {}",
+                id,
                 num_dimensions
             ));
         }
 
-        let mut masks = vec![T::default(); num_blocks];
-        let mut begs = vec![0; num_blocks + 1];
-
-        for b in 0..num_blocks {
-            let dim = (b + num_dimensions) / num_blocks;
-            if 64 == dim {
-                masks[b] = T::from_u64(u64::max_value()).unwrap();
-            } else {
-                masks[b] = T::from_u64((1 << dim) - 1).unwrap();
-            }
-            begs[b + 1] = begs[b] + dim;
-        }
+        let num_blocks = begs.len() - 1;
+        let runs = make_runs(&bits, &begs);
+        let removed = vec![false; codes.len()];
 
-        let mut tables = Vec::<sparsehash::Table>::with_capacity(num_blocks);
+        let mut index = Self {
+            num_dimensions,
+            num_blocks,
+            codes,
+            tables: Vec::with_capacity(num_blocks),
+            begs,
+            bits,
+            runs,
+            removed,
+            num_removed: 0,
+        };
 
         for b in 0..num_blocks {
-            let beg = begs[b];
-            let dim = begs[b + 1] - begs[b];
-
-            let mut table = sparsehash::Table::new(dim)?;
+            let mut table = sparsehash::Table::new(index.get_dim(b))?;
 
-            for &code in &codes {
-                let chunk = (code >> beg) & masks[b];
-                table.count_insert(chunk.to_u64().unwrap() as usize);
+            for code in &index.codes {
+                let chunk = index.get_chunk(code, b);
+                table.count_insert(chunk as usize);
             }
 
-            for (id, &code) in codes.iter().enumerate() {
-                let chunk = (code >> beg) & masks[b];
-                table.data_insert(chunk.to_u64().unwrap() as usize, id as u32);
+            for (id, code) in index.codes.iter().enumerate() {
+                let chunk = index.get_chunk(code, b);
+                table.data_insert(chunk as usize, id as u32);
             }
 
-            tables.push(table);
+            index.tables.push(table);
         }
 
-        Ok(Self {
-            num_blocks,
-            codes,
-            tables,
-            masks,
-            begs,
-        })
+        Ok(index)
     }
 
     /// Returns a searcher [`RangeSearcher`] to find neighbor codes
@@ -138,11 +222,12 @@ impl<T: CodeInt> Index<T> {
     /// let answers = searcher.run(qcode, 2);
     /// assert_eq!(answers, vec![1, 4, 6]);
     /// ```
-    pub fn range_searcher(&self) -> RangeSearcher<T> {
+    pub fn range_searcher(&self) -> RangeSearcher<'_, T> {
         RangeSearcher {
             index: self,
             siggen: siggen::SigGenerator64::new(),
             answers: Vec::with_capacity(1 << 10),
+            neighbors: Vec::with_capacity(1 << 10),
         }
     }
 
@@ -171,16 +256,67 @@ impl<T: CodeInt> Index<T> {
     /// let answers = searcher.run(qcode, 4);
     /// assert_eq!(answers, vec![4, 1, 6, 0]);
     /// ```
-    pub fn topk_searcher(&self) -> TopkSearcher<T> {
+    pub fn topk_searcher(&self) -> TopkSearcher<'_, T> {
         TopkSearcher {
             index: self,
             siggen: siggen::SigGenerator64::new(),
             answers: Vec::with_capacity(1 << 10),
+            neighbors: Vec::with_capacity(1 << 10),
+            checked: std::collections::HashSet::new(),
+            tie_policy: TiePolicy::Arbitrary,
+        }
+    }
+
+    /// Returns a lazy iterator [`NeighborIter`] over the codes in nondecreasing Hamming distances to a query code.
+    ///
+    /// The iterator grows the probing radius of each block in the same manner as [`TopkSearcher::run()`],
+    /// but only as far as needed for the neighbors consumed so far,
+    /// so it is useful when the number of neighbors to be needed is not known in advance.
+    /// Neighbors of the same distance are yielded in the order of ids.
+    /// The codes removed with [`Index::remove()`] are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::{Index, Neighbor};
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let index = Index::with_blocks(codes, 2).unwrap();
+    ///
+    /// // The nearest neighbor whose id is odd
+    /// let found = index.nearest_neighbors(0b0011).find(|n| n.id % 2 == 1);
+    /// assert_eq!(found, Some(Neighbor { id: 3, dist: 1 }));
+    ///
+    /// let ids: Vec<u32> = index.nearest_neighbors(0b0011).map(|n| n.id).collect();
+    /// assert_eq!(ids, vec![2, 3, 0, 1]);
+    /// ```
+    pub fn nearest_neighbors(&self, qcode: T) -> NeighborIter<'_, T> {
+        // The iterator without buckets yields nothing for an invalid query.
+        let buckets = if has_high_bits(&qcode, self.num_dimensions) {
+            vec![]
+        } else {
+            vec![vec![]; self.num_dimensions + 1]
+        };
+        NeighborIter {
+            index: self,
+            qcode,
+            siggen: siggen::SigGenerator64::new(),
             checked: std::collections::HashSet::new(),
+            buckets,
+            block: 0,
+            radius: 0,
+            complete: 0,
+            dist: 0,
+            pos: 0,
         }
     }
 
     /// Gets the reference of the input database.
+    /// The codes removed with [`Index::remove()`] are included until [`Index::compact()`] renumbers the ids.
     ///
     /// # Examples
     ///
@@ -205,275 +341,1399 @@ impl<T: CodeInt> Index<T> {
         &self.codes
     }
 
+    /// Gets the number of meaningful bits in a binary code.
+    pub const fn num_dimensions(&self) -> usize {
+        self.num_dimensions
+    }
+
     /// Gets the number of defined blocks in multi-index.
     pub const fn num_blocks(&self) -> usize {
         self.num_blocks
     }
 
-    /// Serializes the index into the file.
-    pub fn serialize_into<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
-        writer.write_u64::<LittleEndian>(self.num_blocks as u64)?;
-        writer.write_u64::<LittleEndian>(self.codes.len() as u64)?;
-        for x in &self.codes {
-            x.serialize_into(&mut writer)?;
-        }
-        writer.write_u64::<LittleEndian>(self.tables.len() as u64)?;
-        for x in &self.tables {
-            x.serialize_into(&mut writer)?;
-        }
-        writer.write_u64::<LittleEndian>(self.masks.len() as u64)?;
-        for x in &self.masks {
-            x.serialize_into(&mut writer)?;
-        }
-        writer.write_u64::<LittleEndian>(self.begs.len() as u64)?;
-        for &x in &self.begs {
-            writer.write_u64::<LittleEndian>(x as u64)?;
-        }
-        Ok(())
-    }
-
-    /// Deserializes the index from the file.
-    pub fn deserialize_from<R: std::io::Read>(mut reader: R) -> Result<Self> {
-        let num_blocks = reader.read_u64::<LittleEndian>()? as usize;
-        let codes = {
-            let len = reader.read_u64::<LittleEndian>()? as usize;
-            let mut codes = Vec::with_capacity(len);
-            for _ in 0..len {
-                codes.push(T::deserialize_from(&mut reader)?);
-            }
-            codes
-        };
-        let tables = {
-            let len = reader.read_u64::<LittleEndian>()? as usize;
-            let mut tables = Vec::with_capacity(len);
-            for _ in 0..len {
-                tables.push(sparsehash::Table::deserialize_from(&mut reader)?);
-            }
-            tables
-        };
-        let masks = {
-            let len = reader.read_u64::<LittleEndian>()? as usize;
-            let mut masks = Vec::with_capacity(len);
-            for _ in 0..len {
-                masks.push(T::deserialize_from(&mut reader)?);
-            }
-            masks
-        };
-        let begs = {
-            let len = reader.read_u64::<LittleEndian>()? as usize;
-            let mut begs = Vec::with_capacity(len);
-            for _ in 0..len {
-                begs.push(reader.read_u64::<LittleEndian>()? as usize);
-            }
-            begs
-        };
-        Ok(Self {
-            num_blocks,
-            codes,
-            tables,
-            masks,
-            begs,
-        })
-    }
-
-    fn get_dim(&self, b: usize) -> usize {
-        self.begs[b + 1] - self.begs[b]
-    }
-
-    fn get_chunk(&self, code: T, b: usize) -> u64 {
-        let chunk = (code >> self.begs[b]) & self.masks[b];
-        chunk.to_u64().unwrap()
+    /// Gets the bit positions of the `b`-th block in ascending order.
+    ///
+    /// # Panics
+    ///
+    /// It will panic if `b` is no less than [`Index::num_blocks()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let codes: Vec<u16> = vec![0x00FF, 0x0F0F, 0xFF00];
+    /// let index = Index::with_blocks(codes, 3).unwrap();
+    /// assert_eq!(index.block(0), &[0, 1, 2, 3, 4]);
+    /// assert_eq!(index.block(1), &[5, 6, 7, 8, 9]);
+    /// assert_eq!(index.block(2), &[10, 11, 12, 13, 14, 15]);
+    /// ```
+    pub fn block(&self, b: usize) -> &[usize] {
+        &self.bits[self.begs[b]..self.begs[b + 1]]
     }
-}
 
-impl<'a, T> RangeSearcher<'a, T>
-where
-    T: CodeInt,
-{
-    /// Searches neighbor codes whose Hamming distances to a query code are within a query radius.
+    /// Inserts a binary code into the index and returns its id,
+    /// which is the number of codes stored before the insertion.
+    ///
+    /// The code is added to the tables of all the blocks in place,
+    /// so the index does not need to be rebuilt.
+    /// Each table stores the buckets of 64 consecutive chunks in a packed array,
+    /// so an insertion shifts the later entries of the array and takes time
+    /// proportional to `n * 64 / 2^dim` for `n` stored codes and blocks of `dim` bits.
+    /// It is cheap while `2^dim` is close to `n`, but if the index grows far beyond it,
+    /// consider rebuilding the index or using [`crate::SegmentedIndex`].
+    /// The benchmark `timeperf_insert` in the `bench` crate measures the cost.
     ///
     /// # Arguments
     ///
-    /// - `qcode`: Binary code of the query.
-    /// - `radius`: Threshold to be searched.
+    /// - `code`: Binary code to be inserted.
     ///
-    /// # Returns
+    /// # Errors
     ///
-    /// A slice of ids of codes whose Hamming distances to `qcode` are within `radius`.
-    /// The ids are sorted.
-    /// Note that the values of the slice will be updated in the next [`RangeSearcher::run()`].
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the index already has `u32::MAX` codes, or
+    ///  - `code` has a bit set at or above [`Index::num_dimensions()`].
     ///
     /// # Examples
     ///
     /// ```
     /// use mih_rs::Index;
     ///
-    /// let codes: Vec<u64> = vec![
-    ///     0b1111111111111111111111011111111111111111111111111011101111111111, // #zeros = 3
-    ///     0b1111111111111111111111111111111101111111111011111111111111111111, // #zeros = 2
-    ///     0b1111111011011101111111111111111101111111111111111111111111111111, // #zeros = 4
-    ///     0b1111111111111101111111111111111111111000111111111110001111111110, // #zeros = 8
-    ///     0b1101111111111111111111111111111111111111111111111111111111111111, // #zeros = 1
-    ///     0b1111111111111111101111111011111111111111111101001110111111111111, // #zeros = 6
-    ///     0b1111111111111111111111111111111111101111111111111111011111111111, // #zeros = 2
-    ///     0b1110110101011011011111111111111101111111111111111000011111111111, // #zeros = 11
-    /// ];
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111];
+    /// let mut index = Index::with_blocks(codes, 2).unwrap();
     ///
-    /// let index = Index::new(codes).unwrap();
-    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(index.insert(0b0111).unwrap(), 2);
+    /// assert_eq!(index.codes(), &[0b0000, 0b1111, 0b0111]);
     ///
-    /// let qcode: u64 = 0b1111111111111111111111111111111111111111111111111111111111111111; // #zeros = 0
-    /// let answers = searcher.run(qcode, 2);
-    /// assert_eq!(answers, vec![1, 4, 6]);
+    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(searcher.run(0b0011, 1), vec![2]);
     /// ```
-    pub fn run(&mut self, qcode: T, radius: usize) -> &[u32] {
-        self.answers.clear();
-        let num_blocks = self.index.num_blocks();
-
-        for b in 0..num_blocks {
-            // Based on the general pigeonhole principle
-            if b + radius + 1 < num_blocks {
-                continue;
-            }
-
-            let rad = (b + radius + 1 - num_blocks) / num_blocks;
-            let dim = self.index.get_dim(b);
-            let qcd = self.index.get_chunk(qcode, b);
-
-            let table = &self.index.tables[b];
-
-            // Search with r errors
-            for r in 0..rad + 1 {
-                self.siggen.init(qcd, dim, r);
-                while self.siggen.has_next() {
-                    let sig = self.siggen.next();
-                    if let Some(a) = table.access(sig as usize) {
-                        for v in a {
-                            self.answers.push(*v);
-                        }
-                    }
-                }
-            }
-        }
-
-        let mut n = 0;
-        if !self.answers.is_empty() {
-            self.answers.sort_unstable();
-            for i in 0..self.answers.len() {
-                if i == 0 || self.answers[i - 1] != self.answers[i] {
-                    let dist = hamdist(qcode, self.index.codes[self.answers[i] as usize]);
-                    if dist <= radius {
-                        self.answers[n] = self.answers[i];
-                        n += 1;
-                    }
-                }
-            }
-        }
-
-        self.answers.resize(n, u32::default());
-        &self.answers
+    pub fn insert(&mut self, code: T) -> Result<u32> {
+        self.check_insertable(&code, self.codes.len())?;
+        Ok(self.insert_unchecked(code))
     }
-}
 
-impl<'a, T> TopkSearcher<'a, T>
-where
-    T: CodeInt,
-{
-    /// Searches top-K codes that are closest to a query code.
+    /// Inserts binary codes into the index in order.
+    /// The ids are assigned in the same manner as [`Index::insert()`].
+    ///
+    /// The codes are validated before any of them is inserted,
+    /// so the index is unchanged when an error is returned.
     ///
     /// # Arguments
     ///
-    /// - `qcode`: Binary code of the query.
-    /// - `topk`: Threshold to be searched.
+    /// - `codes`: Binary codes to be inserted.
     ///
     /// # Returns
     ///
-    /// A slice of ids of the `topk` nearest neighbor codes to `qcode`.
-    /// The ids are sorted in the Hamming distances to `qcode`.
-    /// Note that the values of the slice will be updated in the next [`TopkSearcher::run()`].
+    /// The range of the assigned ids.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the index would have more than `u32::MAX` codes, or
+    ///  - a code has a bit set at or above [`Index::num_dimensions()`].
     ///
     /// # Examples
     ///
     /// ```
     /// use mih_rs::Index;
     ///
-    /// let codes: Vec<u64> = vec![
-    ///     0b1111111111111111111111011111111111111111111111111011101111111111, // #zeros = 3
-    ///     0b1111111111111111111111111111111101111111111011111111111111111111, // #zeros = 2
-    ///     0b1111111011011101111111111111111101111111111111111111111111111111, // #zeros = 4
-    ///     0b1111111111111101111111111111111111111000111111111110001111111110, // #zeros = 8
-    ///     0b1101111111111111111111111111111111111111111111111111111111111111, // #zeros = 1
-    ///     0b1111111111111111101111111011111111111111111101001110111111111111, // #zeros = 6
-    ///     0b1111111111111111111111111111111111101111111111111111011111111111, // #zeros = 2
-    ///     0b1110110101011011011111111111111101111111111111111000011111111111, // #zeros = 11
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111];
+    /// let mut index = Index::with_blocks(codes, 2).unwrap();
+    ///
+    /// assert_eq!(index.extend(vec![0b0111, 0b0001]).unwrap(), 2..4);
+    ///
+    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(searcher.run(0b0011, 1), vec![2, 3]);
+    /// ```
+    pub fn extend<I>(&mut self, codes: I) -> Result<std::ops::Range<u32>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let codes: Vec<T> = codes.into_iter().collect();
+        let beg = self.codes.len();
+        for (i, code) in codes.iter().enumerate() {
+            self.check_insertable(code, beg + i)?;
+        }
+        self.codes.reserve(codes.len());
+        for code in codes {
+            self.insert_unchecked(code);
+        }
+        Ok(beg as u32..self.codes.len() as u32)
+    }
+
+    /// Merges two indices into one over the concatenated codes,
+    /// where the ids of `b` are shifted by the number of codes in `a`.
+    ///
+    /// The posting lists of the tables are merged directly without hashing the codes again,
+    /// which requires the two indices to have the same block layout,
+    /// i.e., the same number of dimensions and the same bits in each block.
+    /// The codes removed with [`Index::remove()`] remain removed.
+    ///
+    /// # Arguments
+    ///
+    /// - `a`: Index whose ids are kept.
+    /// - `b`: Index whose ids are shifted.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the block layouts of `a` and `b` are different, or
+    ///  - the merged index would have more than `u32::MAX` codes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let a = Index::with_blocks(vec![0b0000u8, 0b1111], 2).unwrap();
+    /// let b = Index::with_blocks(vec![0b0111u8, 0b0001], 2).unwrap();
+    ///
+    /// let index = Index::merge(a, b).unwrap();
+    /// assert_eq!(index.codes(), &[0b0000, 0b1111, 0b0111, 0b0001]);
+    ///
+    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(searcher.run(0b0011, 1), vec![2, 3]);
+    ///
+    /// let c = Index::with_blocks(vec![0b0011u8], 4).unwrap();
+    /// assert!(Index::merge(index, c).is_err());
+    /// ```
+    pub fn merge(a: Self, b: Self) -> Result<Self> {
+        Self::merge_impl(a, b, false)
+    }
+
+    /// Merges two indices in the same manner as [`Index::merge()`],
+    /// but the codes of `b` are partitioned again with the block layout of `a`
+    /// if the layouts are different.
+    ///
+    /// # Arguments
+    ///
+    /// - `a`: Index whose ids and block layout are kept.
+    /// - `b`: Index whose ids are shifted.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - a code of `b` has a bit set at or above the number of dimensions of `a`, or
+    ///  - the merged index would have more than `u32::MAX` codes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let a = Index::with_blocks(vec![0b0000u8, 0b1111], 2).unwrap();
+    /// let b = Index::with_blocks(vec![0b0111u8, 0b0001], 4).unwrap();
+    ///
+    /// let index = Index::merge_repartitioned(a, b).unwrap();
+    /// assert_eq!(index.num_blocks(), 2);
+    ///
+    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(searcher.run(0b0011, 1), vec![2, 3]);
+    /// ```
+    pub fn merge_repartitioned(a: Self, b: Self) -> Result<Self> {
+        Self::merge_impl(a, b, true)
+    }
+
+    fn merge_impl(mut a: Self, b: Self, repartition: bool) -> Result<Self> {
+        if (u32::MAX as usize) < a.codes.len() + b.codes.len() {
+            return Err(anyhow!(
+                "The number of codes {} must not be no more than {}.",
+                a.codes.len() + b.codes.len(),
+                u32::MAX
+            ));
+        }
+
+        let same_layout =
+            a.num_dimensions == b.num_dimensions && a.begs == b.begs && a.bits == b.bits;
+
+        if same_layout {
+            let offset = a.codes.len() as u32;
+            for (table, other) in a.tables.iter_mut().zip(b.tables.iter()) {
+                table.merge(other, offset);
+            }
+            a.codes.extend(b.codes);
+        } else if repartition {
+            for (id, code) in b.codes.iter().enumerate() {
+                if has_high_bits(code, a.num_dimensions) {
+                    return Err(anyhow!(
+                        "The code {} of the second index must not have bits at or above {}",
+                        id,
+                        a.num_dimensions
+                    ));
+                }
+            }
+            a.codes.reserve(b.codes.len());
+            for code in b.codes {
+                a.insert_unchecked(code);
+            }
+            a.removed.truncate(a.removed.len() - b.removed.len());
+        } else {
+            return Err(anyhow!(
+                "The block layouts of the two indices must be the same, \
+                 or the codes must be partitioned again"
+            ));
+        }
+
+        a.removed.extend(b.removed);
+        a.num_removed += b.num_removed;
+        Ok(a)
+    }
+
+    /// Checks if `code` can be inserted with the id `id`.
+    pub(crate) fn check_insertable(&self, code: &T, id: usize) -> Result<()> {
+        if u32::MAX as usize <= id {
+            return Err(anyhow!(
+                "The number of codes must not be no more than {}.",
+                u32::MAX
+            ));
+        }
+        if has_high_bits(code, self.num_dimensions) {
+            return Err(anyhow!(
+                "The code {} must not have bits at or above {}",
+                id,
+                self.num_dimensions
+            ));
+        }
+        Ok(())
+    }
+
+    fn insert_unchecked(&mut self, code: T) -> u32 {
+        let id = self.codes.len() as u32;
+        for b in 0..self.num_blocks {
+            let chunk = self.get_chunk(&code, b);
+            self.tables[b].insert(chunk as usize, id);
+        }
+        self.codes.push(code);
+        self.removed.push(false);
+        id
+    }
+
+    /// Removes the code of an id from the index.
+    ///
+    /// The id is only marked as removed (i.e., tombstoned) and will never be returned by searches,
+    /// but the code still occupies the memory until [`Index::compact()`] is called.
+    /// The ids of the other codes are not changed.
+    ///
+    /// # Arguments
+    ///
+    /// - `id`: Id of the code to be removed.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - `id` is no less than the number of codes, or
+    ///  - `id` has already been removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let mut index = Index::with_blocks(codes, 2).unwrap();
+    ///
+    /// index.remove(2).unwrap();
+    /// assert!(index.is_removed(2));
+    /// assert_eq!(index.num_live_codes(), 3);
+    ///
+    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(searcher.run(0b0011, 1), vec![3]);
+    ///
+    /// let mut searcher = index.topk_searcher();
+    /// assert_eq!(searcher.run(0b0011, 2), vec![3, 0]);
+    /// ```
+    pub fn remove(&mut self, id: u32) -> Result<()> {
+        self.check_live(id)?;
+        self.removed[id as usize] = true;
+        self.num_removed += 1;
+        Ok(())
+    }
+
+    /// Checks if `id` is a code that has not been removed.
+    pub(crate) fn check_live(&self, id: u32) -> Result<()> {
+        let idx = id as usize;
+        if self.codes.len() <= idx {
+            return Err(anyhow!(
+                "The id {} must be less than the number of codes {}",
+                id,
+                self.codes.len()
+            ));
+        }
+        if self.removed[idx] {
+            return Err(anyhow!("The id {} must not have been removed", id));
+        }
+        Ok(())
+    }
+
+    /// Replaces the code of an id with a new one.
+    ///
+    /// The id is moved to the new buckets in the tables of all the blocks,
+    /// so searches after the update see only the new code. The id is not changed.
+    ///
+    /// # Arguments
+    ///
+    /// - `id`: Id of the code to be updated.
+    /// - `code`: New binary code of the id.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - `id` is no less than the number of codes,
+    ///  - `id` has been removed, or
+    ///  - `code` has a bit set at or above [`Index::num_dimensions()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let mut index = Index::with_blocks(codes, 2).unwrap();
+    ///
+    /// index.update(1, 0b0011).unwrap();
+    /// assert_eq!(index.codes(), &[0b0000, 0b0011, 0b0111, 0b0001]);
+    ///
+    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(searcher.run(0b0011, 0), vec![1]);
+    /// assert_eq!(searcher.run(0b1111, 0), vec![]);
+    /// ```
+    pub fn update(&mut self, id: u32, code: T) -> Result<()> {
+        let idx = id as usize;
+        self.check_live(id)?;
+        self.check_insertable(&code, idx)?;
+
+        for b in 0..self.num_blocks {
+            let old_chunk = self.get_chunk(&self.codes[idx], b);
+            let new_chunk = self.get_chunk(&code, b);
+            if old_chunk != new_chunk {
+                let found = self.tables[b].remove(old_chunk as usize, id);
+                debug_assert!(found);
+                self.tables[b].insert(new_chunk as usize, id);
+            }
+        }
+        self.codes[idx] = code;
+        Ok(())
+    }
+
+    /// Checks if the code of an id has been removed with [`Index::remove()`].
+    ///
+    /// # Panics
+    ///
+    /// It will panic if `id` is no less than the number of codes.
+    pub fn is_removed(&self, id: u32) -> bool {
+        self.removed[id as usize]
+    }
+
+    /// Gets the number of codes that have not been removed.
+    pub const fn num_live_codes(&self) -> usize {
+        self.codes.len() - self.num_removed
+    }
+
+    /// Physically removes a single id marked by [`Index::remove()`] from the tables,
+    /// keeping its code and id. Unlike [`Index::compact()`], the cost is independent of the number of codes.
+    pub(crate) fn unlink(&mut self, id: u32) {
+        debug_assert!(self.removed[id as usize]);
+        for b in 0..self.num_blocks {
+            let chunk = self.get_chunk(&self.codes[id as usize], b);
+            self.tables[b].remove(chunk as usize, id);
+        }
+    }
+
+    /// Physically removes the ids marked by [`Index::remove()`] from the tables.
+    ///
+    /// If `renumber` is `false`, the ids of the remaining codes are not changed,
+    /// and the codes of the removed ids are kept in [`Index::codes()`].
+    /// If `renumber` is `true`, the remaining codes are renumbered in order from zero,
+    /// and the codes of the removed ids are released.
+    ///
+    /// # Arguments
+    ///
+    /// - `renumber`: Whether to renumber the remaining codes.
+    ///
+    /// # Returns
+    ///
+    /// A map from old ids to new ids, where the `i`-th entry is `None` if the id `i` has been removed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let mut index = Index::with_blocks(codes, 2).unwrap();
+    ///
+    /// index.remove(1).unwrap();
+    /// let map = index.compact(true);
+    /// assert_eq!(map, vec![Some(0), None, Some(1), Some(2)]);
+    /// assert_eq!(index.codes(), &[0b0000, 0b0111, 0b0001]);
+    ///
+    /// let mut searcher = index.range_searcher();
+    /// assert_eq!(searcher.run(0b0011, 1), vec![1, 2]);
+    /// ```
+    pub fn compact(&mut self, renumber: bool) -> Vec<Option<u32>> {
+        let mut map = Vec::with_capacity(self.codes.len());
+        let mut new_id = 0;
+        for id in 0..self.codes.len() {
+            if self.removed[id] {
+                map.push(None);
+            } else {
+                map.push(Some(if renumber { new_id } else { id as u32 }));
+                new_id += 1;
+            }
+        }
+
+        for table in &mut self.tables {
+            table.retain_map(|id| map[id as usize]);
+        }
+
+        if renumber {
+            let removed = std::mem::take(&mut self.removed);
+            let mut iter = removed.iter();
+            self.codes.retain(|_| !iter.next().unwrap());
+            self.removed = vec![false; self.codes.len()];
+            self.num_removed = 0;
+        }
+        map
+    }
+
+    /// Serializes the index into the file.
+    ///
+    /// The data starts with a magic number and the version of the format,
+    /// which are checked by [`Index::deserialize_from()`].
+    pub fn serialize_into<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u64::<LittleEndian>(FORMAT_VERSION)?;
+        writer.write_u64::<LittleEndian>(self.num_dimensions as u64)?;
+        writer.write_u64::<LittleEndian>(self.num_blocks as u64)?;
+        writer.write_u64::<LittleEndian>(self.codes.len() as u64)?;
+        for x in &self.codes {
+            x.serialize_into(&mut writer)?;
+        }
+        writer.write_u64::<LittleEndian>(self.tables.len() as u64)?;
+        for x in &self.tables {
+            x.serialize_into(&mut writer)?;
+        }
+        writer.write_u64::<LittleEndian>(self.begs.len() as u64)?;
+        for &x in &self.begs {
+            writer.write_u64::<LittleEndian>(x as u64)?;
+        }
+        writer.write_u64::<LittleEndian>(self.bits.len() as u64)?;
+        for &x in &self.bits {
+            writer.write_u64::<LittleEndian>(x as u64)?;
+        }
+        writer.write_u64::<LittleEndian>(self.removed.len() as u64)?;
+        for &x in &self.removed {
+            writer.write_u8(x as u8)?;
+        }
+        Ok(())
+    }
+
+    /// Deserializes the index from the file.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the data does not start with the magic number, e.g., it was serialized by an older version,
+    ///  - the version of the format is not supported,
+    ///  - the data is truncated or inconsistent, or
+    ///  - an I/O error occurs.
+    pub fn deserialize_from<R: std::io::Read>(mut reader: R) -> Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(anyhow!(
+                "The data must start with the magic number of a serialized index \
+                 (the index may have been serialized by an older version)"
+            ));
+        }
+        let version = reader.read_u64::<LittleEndian>()?;
+        if version != FORMAT_VERSION {
+            return Err(anyhow!(
+                "The format version {} must be {}",
+                version,
+                FORMAT_VERSION
+            ));
+        }
+
+        let num_dimensions = reader.read_u64::<LittleEndian>()? as usize;
+        let num_blocks = reader.read_u64::<LittleEndian>()? as usize;
+        let codes = {
+            let len = reader.read_u64::<LittleEndian>()? as usize;
+            let mut codes = Vec::with_capacity(len);
+            for _ in 0..len {
+                codes.push(T::deserialize_from(&mut reader)?);
+            }
+            codes
+        };
+        let tables = {
+            let len = reader.read_u64::<LittleEndian>()? as usize;
+            let mut tables = Vec::with_capacity(len);
+            for _ in 0..len {
+                tables.push(sparsehash::Table::deserialize_from(&mut reader)?);
+            }
+            tables
+        };
+        let begs = {
+            let len = reader.read_u64::<LittleEndian>()? as usize;
+            let mut begs = Vec::with_capacity(len);
+            for _ in 0..len {
+                begs.push(reader.read_u64::<LittleEndian>()? as usize);
+            }
+            begs
+        };
+        let bits = {
+            let len = reader.read_u64::<LittleEndian>()? as usize;
+            let mut bits = Vec::with_capacity(len);
+            for _ in 0..len {
+                bits.push(reader.read_u64::<LittleEndian>()? as usize);
+            }
+            bits
+        };
+        let removed = {
+            let len = reader.read_u64::<LittleEndian>()? as usize;
+            let mut removed = Vec::with_capacity(len);
+            for _ in 0..len {
+                removed.push(reader.read_u8()? != 0);
+            }
+            removed
+        };
+        if tables.len() != num_blocks
+            || begs.len() != num_blocks + 1
+            || num_dimensions < bits.len()
+            || begs.last() != Some(&bits.len())
+            || removed.len() != codes.len()
+            || bits.iter().any(|&bit| num_dimensions <= bit)
+        {
+            return Err(anyhow!("The serialized index must be consistent"));
+        }
+        let num_removed = removed.iter().filter(|&&x| x).count();
+        let runs = make_runs(&bits, &begs);
+        Ok(Self {
+            num_dimensions,
+            num_blocks,
+            codes,
+            tables,
+            begs,
+            bits,
+            runs,
+            removed,
+            num_removed,
+        })
+    }
+
+    pub(super) fn get_dim(&self, b: usize) -> usize {
+        self.begs[b + 1] - self.begs[b]
+    }
+
+    pub(super) fn get_chunk(&self, code: &T, b: usize) -> u64 {
+        let mut chunk = 0;
+        for run in &self.runs[b] {
+            chunk |= code.extract(run.beg, run.end) << run.pos;
+        }
+        chunk
+    }
+}
+
+/// Splits the bits of each block into runs of contiguous bits.
+fn make_runs(bits: &[usize], begs: &[usize]) -> Vec<Vec<Run>> {
+    let mut runs = Vec::with_capacity(begs.len() - 1);
+    for b in 0..begs.len() - 1 {
+        let mut block_runs = Vec::<Run>::new();
+        for (pos, &bit) in bits[begs[b]..begs[b + 1]].iter().enumerate() {
+            match block_runs.last_mut() {
+                Some(run) if run.end == bit && run.end - run.beg < 64 => run.end += 1,
+                _ => block_runs.push(Run {
+                    beg: bit,
+                    end: bit + 1,
+                    pos,
+                }),
+            }
+        }
+        runs.push(block_runs);
+    }
+    runs
+}
+
+/// Checks if a code has a bit set at or above `num_dimensions`.
+pub(crate) fn has_high_bits<T: CodeInt>(code: &T, num_dimensions: usize) -> bool {
+    let width = code.width().min(T::dimensions());
+    let mut beg = num_dimensions;
+    while beg < width {
+        let end = width.min(beg + 64);
+        if code.extract(beg, end) != 0 {
+            return true;
+        }
+        beg = end;
+    }
+    false
+}
+
+impl<'a, T> RangeSearcher<'a, T>
+where
+    T: CodeInt,
+{
+    /// Searches neighbor codes whose Hamming distances to a query code are within a query radius.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `radius`: Threshold to be searched.
+    ///
+    /// # Returns
+    ///
+    /// A slice of ids of codes whose Hamming distances to `qcode` are within `radius`.
+    /// The ids are sorted.
+    /// Note that the values of the slice will be updated in the next [`RangeSearcher::run()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let codes: Vec<u64> = vec![
+    ///     0b1111111111111111111111011111111111111111111111111011101111111111, // #zeros = 3
+    ///     0b1111111111111111111111111111111101111111111011111111111111111111, // #zeros = 2
+    ///     0b1111111011011101111111111111111101111111111111111111111111111111, // #zeros = 4
+    ///     0b1111111111111101111111111111111111111000111111111110001111111110, // #zeros = 8
+    ///     0b1101111111111111111111111111111111111111111111111111111111111111, // #zeros = 1
+    ///     0b1111111111111111101111111011111111111111111101001110111111111111, // #zeros = 6
+    ///     0b1111111111111111111111111111111111101111111111111111011111111111, // #zeros = 2
+    ///     0b1110110101011011011111111111111101111111111111111000011111111111, // #zeros = 11
+    /// ];
+    ///
+    /// let index = Index::new(codes).unwrap();
+    /// let mut searcher = index.range_searcher();
+    ///
+    /// let qcode: u64 = 0b1111111111111111111111111111111111111111111111111111111111111111; // #zeros = 0
+    /// let answers = searcher.run(qcode, 2);
+    /// assert_eq!(answers, vec![1, 4, 6]);
+    /// ```
+    pub fn run(&mut self, qcode: T, radius: usize) -> &[u32] {
+        self.search(qcode, 0, radius);
+        self.answers.clear();
+        self.answers.extend(self.neighbors.iter().map(|n| n.id));
+        &self.answers
+    }
+
+    /// Searches neighbor codes whose Hamming distances to a query code are within a query radius,
+    /// returning their distances together.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `radius`: Threshold to be searched.
+    /// - `order`: Order of the returned neighbors.
+    ///
+    /// # Returns
+    ///
+    /// A slice of the neighbors whose Hamming distances to `qcode` are within `radius`,
+    /// sorted in `order`.
+    /// Note that the values of the slice will be updated in the next search.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::{Index, Neighbor, NeighborOrder};
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let index = Index::with_blocks(codes, 2).unwrap();
+    /// let mut searcher = index.range_searcher();
+    ///
+    /// let answers = searcher.run_neighbors(0b0011, 1, NeighborOrder::Id);
+    /// assert_eq!(answers, vec![Neighbor { id: 2, dist: 1 }, Neighbor { id: 3, dist: 1 }]);
+    ///
+    /// let answers = searcher.run_neighbors(0b0001, 2, NeighborOrder::Distance);
+    /// let ids: Vec<u32> = answers.iter().map(|n| n.id).collect();
+    /// assert_eq!(ids, vec![3, 0, 2]);
+    /// ```
+    pub fn run_neighbors(&mut self, qcode: T, radius: usize, order: NeighborOrder) -> &[Neighbor] {
+        self.run_annulus_neighbors(qcode, 0, radius, order)
+    }
+
+    /// Searches neighbor codes whose Hamming distances to a query code are in `[min_radius, max_radius]`.
+    ///
+    /// The candidates are collected in the same manner as [`RangeSearcher::run()`] with `max_radius`,
+    /// except that the signatures of a block are not probed if the codes found with them
+    /// are too close to reach `min_radius` in any case,
+    /// which occurs when `min_radius` exceeds the number of bits out of the block.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `min_radius`: Lower threshold to be searched.
+    /// - `max_radius`: Upper threshold to be searched.
+    ///
+    /// # Returns
+    ///
+    /// A slice of sorted ids of codes whose Hamming distances to `qcode` are in `[min_radius, max_radius]`.
+    /// Note that the values of the slice will be updated in the next search.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let index = Index::with_blocks(codes, 2).unwrap();
+    /// let mut searcher = index.range_searcher();
+    ///
+    /// assert_eq!(searcher.run_annulus(0b0001, 1, 2), vec![0, 2]);
+    /// ```
+    pub fn run_annulus(&mut self, qcode: T, min_radius: usize, max_radius: usize) -> &[u32] {
+        self.search(qcode, min_radius, max_radius);
+        self.answers.clear();
+        self.answers.extend(self.neighbors.iter().map(|n| n.id));
+        &self.answers
+    }
+
+    /// Searches neighbor codes whose Hamming distances to a query code are in `[min_radius, max_radius]`
+    /// in the same manner as [`RangeSearcher::run_annulus()`], returning their distances together.
+    ///
+    /// # Returns
+    ///
+    /// A slice of the neighbors whose Hamming distances to `qcode` are in `[min_radius, max_radius]`,
+    /// sorted in `order`.
+    /// Note that the values of the slice will be updated in the next search.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::{Index, Neighbor, NeighborOrder};
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let index = Index::with_blocks(codes, 2).unwrap();
+    /// let mut searcher = index.range_searcher();
+    ///
+    /// let answers = searcher.run_annulus_neighbors(0b0000, 1, 3, NeighborOrder::Distance);
+    /// assert_eq!(answers, vec![Neighbor { id: 3, dist: 1 }, Neighbor { id: 2, dist: 3 }]);
+    /// ```
+    pub fn run_annulus_neighbors(
+        &mut self,
+        qcode: T,
+        min_radius: usize,
+        max_radius: usize,
+        order: NeighborOrder,
+    ) -> &[Neighbor] {
+        self.search(qcode, min_radius, max_radius);
+        if order == NeighborOrder::Distance {
+            // The stable sort keeps ties sorted by ids.
+            self.neighbors.sort_by_key(|n| n.dist);
+        }
+        &self.neighbors
+    }
+
+    /// Stores the neighbors in `[min_radius, radius]` sorted by ids in `neighbors`.
+    fn search(&mut self, qcode: T, min_radius: usize, radius: usize) {
+        self.answers.clear();
+        self.neighbors.clear();
+        let num_blocks = self.index.num_blocks();
+        let num_dimensions = self.index.num_dimensions();
+        if has_high_bits(&qcode, num_dimensions) {
+            return;
+        }
+
+        for b in 0..num_blocks {
+            // Based on the general pigeonhole principle
+            if b + radius + 1 < num_blocks {
+                continue;
+            }
+
+            let rad = (b + radius + 1 - num_blocks) / num_blocks;
+            let dim = self.index.get_dim(b);
+            let qcd = self.index.get_chunk(&qcode, b);
+
+            let table = &self.index.tables[b];
+
+            // Search with r errors
+            for r in 0..rad.min(dim) + 1 {
+                // The codes found with r errors are within r + (num_dimensions - dim),
+                // so they cannot reach min_radius.
+                if r + num_dimensions - dim < min_radius {
+                    continue;
+                }
+                self.siggen.init(qcd, dim, r);
+                while self.siggen.has_next() {
+                    let sig = self.siggen.next();
+                    if let Some(a) = table.access(sig as usize) {
+                        for v in a {
+                            self.answers.push(*v);
+                        }
+                    }
+                }
+            }
+        }
+
+        if !self.answers.is_empty() {
+            self.answers.sort_unstable();
+            for i in 0..self.answers.len() {
+                if (i == 0 || self.answers[i - 1] != self.answers[i])
+                    && !self.index.removed[self.answers[i] as usize]
+                {
+                    let dist = qcode.distance(&self.index.codes[self.answers[i] as usize]);
+                    if min_radius <= dist && dist <= radius {
+                        self.neighbors.push(Neighbor {
+                            id: self.answers[i],
+                            dist: dist as u32,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a, T> TopkSearcher<'a, T>
+where
+    T: CodeInt,
+{
+    /// Sets the policy to select codes tied at the K-th distance.
+    /// [`TiePolicy::Arbitrary`] is used by default.
+    ///
+    /// The other policies sort all the verified candidates instead of bucketing at most K of them per distance.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::{Index, TiePolicy};
+    ///
+    /// let codes: Vec<u8> = vec![0b0011, 0b0101, 0b0110, 0b0000];
+    /// let index = Index::with_blocks(codes, 2).unwrap();
+    ///
+    /// let mut searcher = index.topk_searcher().tie_policy(TiePolicy::SmallestId);
+    /// assert_eq!(searcher.run(0b0111, 2), vec![0, 1]);
+    ///
+    /// let mut searcher = index.topk_searcher().tie_policy(TiePolicy::IncludeAll);
+    /// assert_eq!(searcher.run(0b0111, 2), vec![0, 1, 2]);
+    /// ```
+    pub const fn tie_policy(mut self, tie_policy: TiePolicy) -> Self {
+        self.tie_policy = tie_policy;
+        self
+    }
+
+    /// Searches top-K codes that are closest to a query code.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `topk`: Threshold to be searched.
+    ///
+    /// # Returns
+    ///
+    /// A slice of ids of the `topk` nearest neighbor codes to `qcode`.
+    /// The ids are sorted in the Hamming distances to `qcode`.
+    /// If `topk` is more than the number of codes that have not been removed, all of them are returned.
+    /// The codes tied at the `topk`-th distance are selected by [`TopkSearcher::tie_policy()`].
+    /// Note that the values of the slice will be updated in the next [`TopkSearcher::run()`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let codes: Vec<u64> = vec![
+    ///     0b1111111111111111111111011111111111111111111111111011101111111111, // #zeros = 3
+    ///     0b1111111111111111111111111111111101111111111011111111111111111111, // #zeros = 2
+    ///     0b1111111011011101111111111111111101111111111111111111111111111111, // #zeros = 4
+    ///     0b1111111111111101111111111111111111111000111111111110001111111110, // #zeros = 8
+    ///     0b1101111111111111111111111111111111111111111111111111111111111111, // #zeros = 1
+    ///     0b1111111111111111101111111011111111111111111101001110111111111111, // #zeros = 6
+    ///     0b1111111111111111111111111111111111101111111111111111011111111111, // #zeros = 2
+    ///     0b1110110101011011011111111111111101111111111111111000011111111111, // #zeros = 11
+    /// ];
+    ///
+    /// let index = Index::new(codes).unwrap();
+    /// let mut searcher = index.topk_searcher();
+    ///
+    /// let qcode: u64 = 0b1111111111111111111111111111111111111111111111111111111111111111; // #zeros = 0
+    /// let answers = searcher.run(qcode, 4);
+    /// assert_eq!(answers, vec![4, 1, 6, 0]);
+    /// ```
+    pub fn run(&mut self, qcode: T, topk: usize) -> &[u32] {
+        self.search(qcode, topk, usize::MAX);
+        self.answers.clear();
+        self.answers.extend(self.neighbors.iter().map(|n| n.id));
+        &self.answers
+    }
+
+    /// Searches top-K codes that are closest to a query code, returning their distances together.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `topk`: Threshold to be searched.
+    ///
+    /// # Returns
+    ///
+    /// A slice of the `topk` nearest neighbors to `qcode`, sorted by distances.
+    /// If `topk` is more than the number of codes that have not been removed, all of them are returned.
+    /// Note that the values of the slice will be updated in the next search.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::{Index, Neighbor};
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let index = Index::with_blocks(codes, 2).unwrap();
+    /// let mut searcher = index.topk_searcher();
+    ///
+    /// let answers = searcher.run_neighbors(0b0000, 2);
+    /// assert_eq!(answers, vec![Neighbor { id: 0, dist: 0 }, Neighbor { id: 3, dist: 1 }]);
+    /// ```
+    pub fn run_neighbors(&mut self, qcode: T, topk: usize) -> &[Neighbor] {
+        self.search(qcode, topk, usize::MAX);
+        &self.neighbors
+    }
+
+    /// Searches top-K codes that are closest to a query code within a maximum radius.
+    ///
+    /// Unlike [`TopkSearcher::run()`], the probing radius is not raised
+    /// once the pigeonhole bound exceeds `max_radius`,
+    /// so far neighbors are not enumerated even if fewer than `topk` codes are found.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `topk`: Threshold to be searched.
+    /// - `max_radius`: Maximum Hamming distance of the results.
+    ///
+    /// # Returns
+    ///
+    /// A slice of ids of at most `topk` nearest neighbor codes to `qcode` within `max_radius`.
+    /// The ids are sorted in the Hamming distances to `qcode`.
+    /// Note that the values of the slice will be updated in the next search.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::Index;
+    ///
+    /// let codes: Vec<u8> = vec![0b0000, 0b1111, 0b0111, 0b0001];
+    /// let index = Index::with_blocks(codes, 2).unwrap();
+    /// let mut searcher = index.topk_searcher();
+    ///
+    /// assert_eq!(searcher.run_bounded(0b0000, 3, 1), vec![0, 3]);
+    /// assert_eq!(searcher.run_bounded(0b0000, 1, 1), vec![0]);
+    /// ```
+    pub fn run_bounded(&mut self, qcode: T, topk: usize, max_radius: usize) -> &[u32] {
+        self.search(qcode, topk, max_radius);
+        self.answers.clear();
+        self.answers.extend(self.neighbors.iter().map(|n| n.id));
+        &self.answers
+    }
+
+    /// Stores the top-K neighbors within `max_radius` sorted by distances in `neighbors`.
+    fn search(&mut self, qcode: T, topk: usize, max_radius: usize) {
+        self.neighbors.clear();
+        let topk = topk.min(self.index.num_live_codes());
+        let num_blocks = self.index.num_blocks();
+        let num_dimensions = self.index.num_dimensions();
+        let max_radius = max_radius.min(num_dimensions);
+        // Such a query has distances over num_dimensions, which the buckets below cannot hold.
+        if has_high_bits(&qcode, num_dimensions) {
+            return;
+        }
+
+        let mut n = 0;
+        let mut r = 0;
+
+        let mut counts = vec![0; num_dimensions + 1];
+
+        // Buckets of at most topk ids for each distance, or all the candidates for the deterministic policies
+        let bucketing = self.tie_policy == TiePolicy::Arbitrary;
+        if bucketing {
+            self.answers
+                .resize((num_dimensions + 1) * topk, u32::default());
+        }
+        self.checked.clear();
+
+        'probe: while n < topk {
+            for b in 0..num_blocks {
+                let dim = self.index.get_dim(b);
+                let qcd = self.index.get_chunk(&qcode, b);
+                let table = &self.index.tables[b];
+
+                // A block narrower than r has no signature with r errors.
+                if r <= dim {
+                    self.siggen.init(qcd, dim, r);
+                    while self.siggen.has_next() {
+                        let sig = self.siggen.next();
+                        if let Some(a) = table.access(sig as usize) {
+                            for &v in a {
+                                let id = v as usize;
+                                if !self.index.removed[id] && self.checked.insert(id) {
+                                    let dist = qcode.distance(&self.index.codes[id]);
+                                    if !bucketing {
+                                        self.neighbors.push(Neighbor {
+                                            id: id as u32,
+                                            dist: dist as u32,
+                                        });
+                                    } else if counts[dist] < topk {
+                                        self.answers[dist * topk + counts[dist]] = id as u32;
+                                    }
+                                    counts[dist] += 1;
+                                }
+                            }
+                        }
+                    }
+                }
+
+                // Based on the general pigeonhole principle,
+                // all the codes within r * num_blocks + b have been found.
+                n += counts[r * num_blocks + b];
+                if topk <= n || max_radius <= r * num_blocks + b {
+                    break 'probe;
+                }
+            }
+
+            r += 1;
+        }
+
+        if !bucketing {
+            // The candidates within the complete distances include all the ties at the topk-th distance.
+            self.neighbors.sort_unstable_by_key(|n| (n.dist, n.id));
+            let mut len = self
+                .neighbors
+                .partition_point(|n| n.dist as usize <= max_radius);
+            if topk < len {
+                len = match self.tie_policy {
+                    TiePolicy::IncludeAll => {
+                        let dist = self.neighbors[topk - 1].dist;
+                        self.neighbors.partition_point(|n| n.dist <= dist)
+                    }
+                    _ => topk,
+                };
+            }
+            self.neighbors.truncate(len);
+            return;
+        }
+
+        r = 0;
+        while self.neighbors.len() < topk && r <= max_radius {
+            let mut i = 0;
+            while i < counts[r] && self.neighbors.len() < topk {
+                self.neighbors.push(Neighbor {
+                    id: self.answers[r * topk + i],
+                    dist: r as u32,
+                });
+                i += 1;
+            }
+            r += 1;
+        }
+    }
+}
+
+impl<'a, T> NeighborIter<'a, T>
+where
+    T: CodeInt,
+{
+    /// Probes the next block with the next radius and updates the complete distance.
+    fn probe(&mut self) {
+        let index = self.index;
+        let num_blocks = index.num_blocks();
+        let (b, r) = (self.block, self.radius);
+
+        let dim = index.get_dim(b);
+        if r <= dim {
+            let qcd = index.get_chunk(&self.qcode, b);
+            self.siggen.init(qcd, dim, r);
+            while self.siggen.has_next() {
+                let sig = self.siggen.next();
+                if let Some(a) = index.tables[b].access(sig as usize) {
+                    for &id in a {
+                        if !index.removed[id as usize] && self.checked.insert(id) {
+                            let dist = self.qcode.distance(&index.codes[id as usize]);
+                            self.buckets[dist].push(id);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Based on the general pigeonhole principle,
+        // all the neighbors within r * num_blocks + b have been found.
+        self.complete = if self.checked.len() == index.num_live_codes() {
+            index.num_dimensions() + 1
+        } else {
+            (r * num_blocks + b + 1).min(index.num_dimensions() + 1)
+        };
+
+        self.block += 1;
+        if self.block == num_blocks {
+            self.block = 0;
+            self.radius += 1;
+        }
+    }
+}
+
+impl<'a, T> Iterator for NeighborIter<'a, T>
+where
+    T: CodeInt,
+{
+    type Item = Neighbor;
+
+    fn next(&mut self) -> Option<Neighbor> {
+        loop {
+            if self.buckets.len() <= self.dist {
+                return None;
+            }
+            if self.dist < self.complete {
+                if self.pos == 0 {
+                    self.buckets[self.dist].sort_unstable();
+                }
+                if let Some(&id) = self.buckets[self.dist].get(self.pos) {
+                    self.pos += 1;
+                    return Some(Neighbor {
+                        id,
+                        dist: self.dist as u32,
+                    });
+                }
+                self.buckets[self.dist] = vec![];
+                self.dist += 1;
+                self.pos = 0;
+            } else {
+                self.probe();
+            }
+        }
+    }
+}
+
+impl<const W: usize> Index<WideCode<W>> {
+    /// Builds an index from byte arrays such as digests of content hashes.
+    /// Each byte array is converted into [`WideCode`] with [`WideCode::from_bytes()`],
+    /// and the number of dimensions is set to the number of bits in a byte array.
+    ///
+    /// # Arguments
+    ///
+    /// - `digests`: Byte arrays of the same length, at most `8 * W` bytes.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the `digests` is empty,
+    ///  - the byte arrays have different lengths or are longer than `8 * W` bytes, or
+    ///  - the other conditions of [`Index::with_dimensions()`] are not satisfied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::{ls, Index, WideCode};
+    ///
+    /// // 20-byte digests
+    /// let digests: Vec<[u8; 20]> = vec![[0x00; 20], [0xFF; 20], [0x0F; 20], [0x01; 20]];
+    ///
+    /// let index = Index::<WideCode<3>>::from_bytes(&digests).unwrap();
+    /// assert_eq!(index.num_dimensions(), 160);
+    ///
+    /// let mut searcher = index.range_searcher();
+    /// let answers = searcher.run_bytes(&[0x00; 20], 20).unwrap();
+    /// assert_eq!(answers, vec![0, 3]);
+    ///
+    /// // Linear search on the same codes
+    /// let qcode = WideCode::from_bytes(&[0x00; 20]).unwrap();
+    /// assert_eq!(ls::range_search(index.codes(), qcode, 20), vec![0, 3]);
+    /// ```
+    pub fn from_bytes<B: AsRef<[u8]>>(digests: &[B]) -> Result<Self> {
+        if digests.is_empty() {
+            return Err(anyhow!("The input codes must not be empty"));
+        }
+
+        let num_bytes = digests[0].as_ref().len();
+        let mut codes = Vec::with_capacity(digests.len());
+        for digest in digests {
+            let digest = digest.as_ref();
+            if digest.len() != num_bytes {
+                return Err(anyhow!(
+                    "The number of bytes {} must be {}",
+                    digest.len(),
+                    num_bytes
+                ));
+            }
+            codes.push(WideCode::from_bytes(digest)?);
+        }
+
+        Self::with_dimensions(codes, 8 * num_bytes)
+    }
+
+    /// Converts a query byte array with [`WideCode::from_bytes()`],
+    /// checking that it has as many bytes as the stored codes.
+    fn query_from_bytes(&self, qcode: &[u8]) -> Result<WideCode<W>> {
+        let num_bytes = self.num_dimensions.div_ceil(8);
+        if qcode.len() != num_bytes {
+            return Err(anyhow!(
+                "The number of bytes {} of the query must be {}",
+                qcode.len(),
+                num_bytes
+            ));
+        }
+        WideCode::from_bytes(qcode)
+    }
+}
+
+impl<'a, const W: usize> RangeSearcher<'a, WideCode<W>> {
+    /// Searches neighbor codes whose Hamming distances to a query byte array are within a query radius.
+    /// The byte array is converted with [`WideCode::from_bytes()`].
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when the length of `qcode` is not
+    /// `ceil(num_dimensions / 8)` bytes, i.e., the length of the digests given to [`Index::from_bytes()`].
+    pub fn run_bytes(&mut self, qcode: &[u8], radius: usize) -> Result<&[u32]> {
+        let qcode = self.index.query_from_bytes(qcode)?;
+        Ok(self.run(qcode, radius))
+    }
+}
+
+impl<'a, const W: usize> TopkSearcher<'a, WideCode<W>> {
+    /// Searches top-K codes that are closest to a query byte array.
+    /// The byte array is converted with [`WideCode::from_bytes()`].
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned in the same cases as [`RangeSearcher::run_bytes()`].
+    pub fn run_bytes(&mut self, qcode: &[u8], topk: usize) -> Result<&[u32]> {
+        let qcode = self.index.query_from_bytes(qcode)?;
+        Ok(self.run(qcode, topk))
+    }
+}
+
+impl Index<DynCode> {
+    /// Builds an index from binary codes of `num_dimensions` bits packed in `codes`.
+    /// Each code is converted into [`DynCode`], and the number of blocks for multi-index
+    /// is set to the optimal one estimated from the number of input codes.
+    ///
+    /// # Arguments
+    ///
+    /// - `codes`: Flat vector of packed binary codes, each of which occupies `ceil(num_dimensions / 64)` words.
+    /// - `num_dimensions`: The number of bits in a binary code.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - `num_dimensions` is zero or more than [`DynCode::MAX_DIMENSIONS`],
+    ///  - the length of `codes` is not a multiple of the number of words in a code, or
+    ///  - the other conditions of [`Index::with_dimensions()`] are not satisfied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use mih_rs::DynIndex;
+    ///
+    /// // Database of 96-bit codes, each packed in two words
+    /// let codes: Vec<u64> = vec![
+    ///     u64::MAX, 0xFFFFFFFE, // #zeros = 1
+    ///     u64::MAX, 0xFFFF0000, // #zeros = 16
+    ///     u64::MAX - 0b11, 0xFFFFFFFF, // #zeros = 2
+    ///     0, 0, // #zeros = 96
     /// ];
     ///
-    /// let index = Index::new(codes).unwrap();
-    /// let mut searcher = index.topk_searcher();
+    /// let index = DynIndex::from_words(codes, 96).unwrap();
+    /// assert_eq!(index.num_dimensions(), 96);
+    /// assert_eq!(index.codes()[1].words(), &[u64::MAX, 0xFFFF0000]);
     ///
-    /// let qcode: u64 = 0b1111111111111111111111111111111111111111111111111111111111111111; // #zeros = 0
-    /// let answers = searcher.run(qcode, 4);
-    /// assert_eq!(answers, vec![4, 1, 6, 0]);
+    /// let mut searcher = index.range_searcher();
+    /// let answers = searcher.run_words(&[u64::MAX, 0xFFFFFFFF], 2).unwrap();
+    /// assert_eq!(answers, vec![0, 2]);
     /// ```
-    pub fn run(&mut self, qcode: T, topk: usize) -> &[u32] {
-        let num_blocks = self.index.num_blocks();
-        let num_dimensions = T::dimensions();
-
-        let mut n = 0;
-        let mut r = 0;
-
-        let mut counts = vec![0; num_dimensions + 1];
-
-        self.answers
-            .resize((num_dimensions + 1) * topk, u32::default());
-        self.checked.clear();
-
-        while n < topk {
-            for b in 0..num_blocks {
-                let dim = self.index.get_dim(b);
-                let qcd = self.index.get_chunk(qcode, b);
-                let table = &self.index.tables[b];
-
-                self.siggen.init(qcd, dim, r);
-                while self.siggen.has_next() {
-                    let sig = self.siggen.next();
-                    if let Some(a) = table.access(sig as usize) {
-                        for &v in a {
-                            let id = v as usize;
-                            if self.checked.insert(id) {
-                                let dist = hamdist(qcode, self.index.codes[id]);
-                                if counts[dist] < topk {
-                                    self.answers[dist * topk + counts[dist]] = id as u32;
-                                }
-                                counts[dist] += 1;
-                            }
-                        }
-                    }
-                }
+    pub fn from_words(codes: Vec<u64>, num_dimensions: usize) -> Result<Self> {
+        Self::with_dimensions(split_words(codes, num_dimensions)?, num_dimensions)
+    }
 
-                n += counts[r * num_blocks + b];
-                if topk <= n {
-                    break;
-                }
-            }
+    /// Builds an index from binary codes of `num_dimensions` bits packed in `codes`
+    /// with a manually specified number of blocks.
+    ///
+    /// # Arguments
+    ///
+    /// - `codes`: Flat vector of packed binary codes, each of which occupies `ceil(num_dimensions / 64)` words.
+    /// - `num_dimensions`: The number of bits in a binary code.
+    /// - `num_blocks`: The number of blocks for multi-index.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - `num_dimensions` is zero or more than [`DynCode::MAX_DIMENSIONS`],
+    ///  - the length of `codes` is not a multiple of the number of words in a code, or
+    ///  - the other conditions of [`Index::with_dimensions_and_blocks()`] are not satisfied.
+    pub fn from_words_with_blocks(
+        codes: Vec<u64>,
+        num_dimensions: usize,
+        num_blocks: usize,
+    ) -> Result<Self> {
+        Self::with_dimensions_and_blocks(
+            split_words(codes, num_dimensions)?,
+            num_dimensions,
+            num_blocks,
+        )
+    }
 
-            r += 1;
+    /// Converts a packed query code into [`DynCode`],
+    /// checking that it has as many words as the stored codes.
+    fn query_from_words(&self, qcode: &[u64]) -> Result<DynCode> {
+        let num_words = self.num_dimensions.div_ceil(64);
+        if qcode.len() != num_words {
+            return Err(anyhow!(
+                "The number of words {} of the query must be {}",
+                qcode.len(),
+                num_words
+            ));
         }
+        Ok(DynCode::from(qcode))
+    }
+}
 
-        n = 0;
-        r = 0;
-        while n < topk {
-            let mut i = 0;
-            while i < counts[r] && n < topk {
-                self.answers[n] = self.answers[r * topk + i];
-                i += 1;
-                n += 1;
-            }
-            r += 1;
-        }
+/// Splits codes of `num_dimensions` bits packed in `codes` into [`DynCode`].
+fn split_words(codes: Vec<u64>, num_dimensions: usize) -> Result<Vec<DynCode>> {
+    if num_dimensions == 0 || DynCode::MAX_DIMENSIONS < num_dimensions {
+        return Err(anyhow!(
+            "The number of dimensions {} must be in [1,{}]",
+            num_dimensions,
+            DynCode::MAX_DIMENSIONS
+        ));
+    }
+    let num_words = num_dimensions.div_ceil(64);
+    if !codes.len().is_multiple_of(num_words) {
+        return Err(anyhow!(
+            "The number of words {} must be a multiple of {}",
+            codes.len(),
+            num_words
+        ));
+    }
+    Ok(codes.chunks(num_words).map(DynCode::from).collect())
+}
 
-        self.answers.resize(topk, u32::default());
-        &self.answers
+impl<'a> RangeSearcher<'a, DynCode> {
+    /// Searches neighbor codes whose Hamming distances to a packed query code are within a query radius.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when the length of `qcode` is not
+    /// `ceil(num_dimensions / 64)` words, i.e., the length of a code given to [`Index::from_words()`].
+    pub fn run_words(&mut self, qcode: &[u64], radius: usize) -> Result<&[u32]> {
+        let qcode = self.index.query_from_words(qcode)?;
+        Ok(self.run(qcode, radius))
+    }
+}
+
+impl<'a> TopkSearcher<'a, DynCode> {
+    /// Searches top-K codes that are closest to a packed query code.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned in the same cases as [`RangeSearcher::run_words()`].
+    pub fn run_words(&mut self, qcode: &[u64], topk: usize) -> Result<&[u32]> {
+        let qcode = self.index.query_from_words(qcode)?;
+        Ok(self.run(qcode, topk))
     }
 }
 
@@ -487,6 +1747,14 @@ mod tests {
 
     use std::collections::BTreeSet;
 
+    impl<const W: usize> Distribution<WideCode<W>> for Standard {
+        fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> WideCode<W> {
+            let mut words = [0; W];
+            rng.fill(&mut words[..]);
+            WideCode(words)
+        }
+    }
+
     pub fn gen_random_codes<T>(size: usize) -> Vec<T>
     where
         Standard: Distribution<T>,
@@ -517,12 +1785,16 @@ mod tests {
 
     fn do_range_search<T: CodeInt>(codes: Vec<T>) {
         let index = Index::new(codes).unwrap();
+        do_range_search_on(&index);
+    }
+
+    fn do_range_search_on<T: CodeInt>(index: &Index<T>) {
         let mut searcher = index.range_searcher();
 
         for rad in 0..6 {
             for qi in (0..10000).step_by(100) {
-                let qcode = index.codes()[qi];
-                let ans1 = ls::range_search(index.codes(), qcode, rad);
+                let qcode = index.codes()[qi].clone();
+                let ans1 = ls::range_search(index.codes(), qcode.clone(), rad);
                 let ans2 = searcher.run(qcode, rad);
                 assert_eq!(ans1, ans2);
             }
@@ -531,16 +1803,25 @@ mod tests {
 
     fn do_topk_search<T: CodeInt>(codes: Vec<T>) {
         let index = Index::new(codes).unwrap();
+        do_topk_search_on(&index);
+    }
+
+    fn do_topk_search_on<T: CodeInt>(index: &Index<T>) {
         let mut searcher = index.topk_searcher();
+        let mut smallest_searcher = index.topk_searcher().tie_policy(TiePolicy::SmallestId);
+        let mut all_searcher = index.topk_searcher().tie_policy(TiePolicy::IncludeAll);
 
         for topk in &[1, 10, 100] {
             for qi in (0..10000).step_by(100) {
-                let qcode = index.codes()[qi];
-                let ans1 = naive_topk_search(index.codes(), qcode, *topk);
+                let qcode = index.codes()[qi].clone();
+                let ans1 = naive_topk_search(index.codes(), qcode.clone(), *topk);
+                assert_eq!(all_searcher.run(qcode.clone(), *topk), &ans1[..]);
+                assert_eq!(smallest_searcher.run(qcode.clone(), *topk), &ans1[..*topk]);
+
                 let ans2 = searcher.run(qcode, *topk);
                 let set1: BTreeSet<u32> = ans1.into_iter().collect();
-                let set2: BTreeSet<u32> = ans2.into_iter().cloned().collect();
-                assert_eq!(set2.is_subset(&set1), true);
+                let set2: BTreeSet<u32> = ans2.iter().cloned().collect();
+                assert!(set2.is_subset(&set1));
             }
         }
     }
@@ -568,6 +1849,23 @@ mod tests {
         let codes = gen_random_codes::<u64>(10000);
         do_range_search(codes);
     }
+    #[test]
+    fn range_search_u128_works() {
+        let codes = gen_random_codes::<u128>(10000);
+        do_range_search(codes);
+    }
+
+    #[test]
+    fn range_search_w256_works() {
+        let codes = gen_random_codes::<WideCode<4>>(10000);
+        do_range_search(codes);
+    }
+
+    #[test]
+    fn range_search_w512_works() {
+        let codes = gen_random_codes::<WideCode<8>>(10000);
+        do_range_search(codes);
+    }
 
     #[test]
     fn topk_search_u8_works() {
@@ -592,6 +1890,150 @@ mod tests {
         let codes = gen_random_codes::<u64>(10000);
         do_topk_search(codes);
     }
+    #[test]
+    fn topk_search_u128_works() {
+        let codes = gen_random_codes::<u128>(10000);
+        do_topk_search(codes);
+    }
+
+    #[test]
+    fn topk_search_w256_works() {
+        let codes = gen_random_codes::<WideCode<4>>(10000);
+        do_topk_search(codes);
+    }
+
+    #[test]
+    fn topk_search_w512_works() {
+        let codes = gen_random_codes::<WideCode<8>>(10000);
+        do_topk_search(codes);
+    }
+
+    #[test]
+    fn neighbors_work() {
+        let codes = gen_random_codes::<u32>(10000);
+        let index = Index::new(codes.clone()).unwrap();
+        let mut range_searcher = index.range_searcher();
+        let mut topk_searcher = index.topk_searcher();
+
+        for qi in (0..10000).step_by(100) {
+            let qcode = codes[qi];
+            let ids = ls::range_search(&codes, qcode, 6);
+            let neighbors = range_searcher.run_neighbors(qcode, 6, NeighborOrder::Id);
+            assert_eq!(neighbors.iter().map(|n| n.id).collect::<Vec<_>>(), ids);
+            for n in neighbors {
+                assert_eq!(n.dist as usize, codes[n.id as usize].distance(&qcode));
+            }
+
+            let mut expected = neighbors.to_vec();
+            expected.sort_by_key(|n| (n.dist, n.id));
+            let neighbors = range_searcher.run_neighbors(qcode, 6, NeighborOrder::Distance);
+            assert_eq!(neighbors, &expected[..]);
+
+            let mut dists: Vec<u32> = codes.iter().map(|c| c.distance(&qcode) as u32).collect();
+            dists.sort_unstable();
+            let neighbors = topk_searcher.run_neighbors(qcode, 10);
+            assert_eq!(
+                neighbors.iter().map(|n| n.dist).collect::<Vec<_>>(),
+                &dists[..10]
+            );
+            for n in neighbors {
+                assert_eq!(n.dist as usize, codes[n.id as usize].distance(&qcode));
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_topk_search_works() {
+        let codes = gen_random_codes::<u32>(10000);
+        let mut index = Index::new(codes.clone()).unwrap();
+        for id in (0..10000).step_by(3) {
+            index.remove(id).unwrap();
+        }
+        let mut searcher = index.topk_searcher();
+        let mut smallest_searcher = index.topk_searcher().tie_policy(TiePolicy::SmallestId);
+
+        for qi in (0..10000).step_by(100) {
+            let qcode = codes[qi];
+            for (topk, max_radius) in [(1, 0), (10, 3), (100, 5), (10, 32)] {
+                let mut dists: Vec<usize> = (0..10000)
+                    .filter(|&id| !index.is_removed(id))
+                    .map(|id| codes[id as usize].distance(&qcode))
+                    .filter(|&dist| dist <= max_radius)
+                    .collect();
+                dists.sort_unstable();
+                dists.truncate(topk);
+
+                let answers = searcher.run_bounded(qcode, topk, max_radius);
+                let answer_dists: Vec<usize> = answers
+                    .iter()
+                    .map(|&id| codes[id as usize].distance(&qcode))
+                    .collect();
+                assert_eq!(answer_dists, dists);
+
+                let mut expected: Vec<(usize, u32)> = (0..10000)
+                    .filter(|&id| !index.is_removed(id))
+                    .map(|id| (codes[id as usize].distance(&qcode), id))
+                    .filter(|&(dist, _)| dist <= max_radius)
+                    .collect();
+                expected.sort_unstable();
+                expected.truncate(topk);
+                let expected: Vec<u32> = expected.into_iter().map(|(_, id)| id).collect();
+                assert_eq!(
+                    smallest_searcher.run_bounded(qcode, topk, max_radius),
+                    &expected[..]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn annulus_search_works() {
+        let codes = gen_random_codes::<u16>(10000);
+        let index = Index::with_blocks(codes.clone(), 4).unwrap();
+        let mut searcher = index.range_searcher();
+
+        for qi in (0..10000).step_by(100) {
+            let qcode = codes[qi];
+            for (min, max) in [(0, 3), (2, 5), (5, 5), (13, 16), (6, 2)] {
+                let expected: Vec<u32> = (0..10000)
+                    .filter(|&id| (min..=max).contains(&codes[id as usize].distance(&qcode)))
+                    .collect();
+                assert_eq!(searcher.run_annulus(qcode, min, max), &expected[..]);
+                let neighbors = searcher.run_annulus_neighbors(qcode, min, max, NeighborOrder::Id);
+                assert_eq!(neighbors.iter().map(|n| n.id).collect::<Vec<_>>(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_neighbors_work() {
+        let codes = gen_random_codes::<u32>(10000);
+        let mut index = Index::new(codes.clone()).unwrap();
+        for id in (0..10000).step_by(3) {
+            index.remove(id).unwrap();
+        }
+
+        for qi in (0..10000).step_by(1000) {
+            let qcode = codes[qi];
+            let mut expected: Vec<Neighbor> = (0..10000)
+                .filter(|&id| !index.is_removed(id))
+                .map(|id| Neighbor {
+                    id,
+                    dist: codes[id as usize].distance(&qcode) as u32,
+                })
+                .collect();
+            expected.sort_by_key(|n| (n.dist, n.id));
+
+            let mut iter = index.nearest_neighbors(qcode);
+            let first: Vec<Neighbor> = iter.by_ref().take(10).collect();
+            assert_eq!(first, &expected[..10]);
+            // Only a part of the codes has been verified.
+            assert!(iter.checked.len() < index.num_live_codes());
+
+            let rest: Vec<Neighbor> = iter.collect();
+            assert_eq!(rest, &expected[10..]);
+        }
+    }
 
     #[test]
     fn serialize_u8_works() {
@@ -605,6 +2047,27 @@ mod tests {
         assert_eq!(index, other);
     }
 
+    #[test]
+    fn deserialize_invalid_data_fails() {
+        let index = Index::with_blocks(vec![0u16, 1, 3, 7, 0xFFFF], 4).unwrap();
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+
+        // Truncated data
+        for len in 0..data.len() {
+            assert!(Index::<u16>::deserialize_from(&data[..len]).is_err());
+        }
+
+        // Data in the old format without the header
+        assert!(Index::<u16>::deserialize_from(&data[16..]).is_err());
+
+        // Unsupported version
+        let mut other = data.clone();
+        other[8] += 1;
+        let e = Index::<u16>::deserialize_from(&other[..]).unwrap_err();
+        assert!(e.to_string().contains("version"));
+    }
+
     #[test]
     fn serialize_u16_works() {
         let codes = gen_random_codes::<u16>(10000);
@@ -640,4 +2103,357 @@ mod tests {
 
         assert_eq!(index, other);
     }
+
+    #[test]
+    fn serialize_u128_works() {
+        let codes = gen_random_codes::<u128>(10000);
+        let index = Index::new(codes).unwrap();
+
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+        let other = Index::<u128>::deserialize_from(&data[..]).unwrap();
+
+        assert_eq!(index, other);
+    }
+
+    #[test]
+    fn serialize_w256_works() {
+        let codes = gen_random_codes::<WideCode<4>>(10000);
+        let index = Index::new(codes).unwrap();
+
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+        let other = Index::<WideCode<4>>::deserialize_from(&data[..]).unwrap();
+
+        assert_eq!(index, other);
+    }
+
+    #[test]
+    fn with_dimensions_works() {
+        let codes: Vec<u64> = gen_random_codes::<u64>(10000)
+            .into_iter()
+            .map(|x| x >> 16)
+            .collect();
+        let index = Index::with_dimensions(codes, 48).unwrap();
+        assert_eq!(index.num_dimensions(), 48);
+
+        do_range_search_on(&index);
+        do_topk_search_on(&index);
+
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+        let other = Index::<u64>::deserialize_from(&data[..]).unwrap();
+        assert_eq!(index, other);
+    }
+
+    #[test]
+    fn high_bit_query_finds_nothing() {
+        let codes: Vec<u64> = vec![0, 1, 3, 7, 0xFFFF_FFFF_FFFF];
+        let index = Index::with_dimensions(codes, 48).unwrap();
+        let qcode = 1 << 50 | 1;
+
+        let mut searcher = index.range_searcher();
+        assert!(searcher.run(qcode, 64).is_empty());
+        assert!(searcher.run_annulus(qcode, 10, 64).is_empty());
+        let mut searcher = index.topk_searcher();
+        assert!(searcher.run(qcode, 3).is_empty());
+        assert!(searcher.run_bounded(qcode, 3, 10).is_empty());
+        let mut searcher = index.topk_searcher().tie_policy(TiePolicy::IncludeAll);
+        assert!(searcher.run(qcode, 3).is_empty());
+        assert_eq!(index.nearest_neighbors(qcode).next(), None);
+
+        // The valid query still works.
+        let mut searcher = index.topk_searcher();
+        assert_eq!(searcher.run(7, 1), &[3]);
+    }
+
+    #[test]
+    fn with_dimensions_fails() {
+        assert!(Index::with_dimensions(vec![0u64, 1 << 48], 48).is_err());
+        assert!(Index::with_dimensions(vec![0u64, 1 << 47], 48).is_ok());
+        assert!(Index::with_dimensions(vec![0u64, 1], 0).is_err());
+        assert!(Index::with_dimensions(vec![0u64, 1], 65).is_err());
+        assert!(Index::with_dimensions_and_blocks(vec![0u32, 1], 8, 9).is_err());
+    }
+
+    #[test]
+    fn from_bytes_works() {
+        let mut rng = thread_rng();
+        let digests: Vec<[u8; 20]> = (0..10000).map(|_| rng.gen()).collect();
+        let index = Index::<WideCode<3>>::from_bytes(&digests).unwrap();
+        assert_eq!(index.num_dimensions(), 160);
+
+        let mut searcher = index.range_searcher();
+        for qi in (0..10000).step_by(100) {
+            let qcode = WideCode::from_bytes(&digests[qi]).unwrap();
+            assert_eq!(&qcode.to_bytes()[..20], &digests[qi][..]);
+            let ans1 = ls::range_search(index.codes(), qcode, 5);
+            let ans2 = searcher.run_bytes(&digests[qi], 5).unwrap();
+            assert_eq!(ans1, ans2);
+        }
+
+        assert!(Index::<WideCode<3>>::from_bytes(&[vec![0u8; 20], vec![0u8; 19]]).is_err());
+        assert!(Index::<WideCode<2>>::from_bytes(&digests).is_err());
+    }
+
+    #[test]
+    fn run_bytes_fails() {
+        let digests: Vec<[u8; 20]> = vec![[0x00; 20], [0xFF; 20]];
+        let index = Index::<WideCode<3>>::from_bytes(&digests).unwrap();
+        let mut range_searcher = index.range_searcher();
+        let mut topk_searcher = index.topk_searcher();
+        for len in [19, 21, 24, 25, 33] {
+            assert!(range_searcher.run_bytes(&vec![0; len], 0).is_err());
+            assert!(topk_searcher.run_bytes(&vec![0; len], 1).is_err());
+        }
+        assert_eq!(topk_searcher.run_bytes(&[0; 20], 1).unwrap(), &[0]);
+
+        let digests: Vec<[u8; 32]> = vec![[0x00; 32], [0xFF; 32]];
+        let index = Index::<WideCode<4>>::from_bytes(&digests).unwrap();
+        let mut range_searcher = index.range_searcher();
+        let mut topk_searcher = index.topk_searcher();
+        for len in [19, 31, 33] {
+            assert!(range_searcher.run_bytes(&vec![0; len], 0).is_err());
+            assert!(topk_searcher.run_bytes(&vec![0; len], 1).is_err());
+        }
+        assert_eq!(range_searcher.run_bytes(&[0xFF; 32], 0).unwrap(), &[1]);
+    }
+
+    #[test]
+    fn insert_works() {
+        let codes = gen_random_codes::<u64>(10000);
+        let mut index = Index::new(codes[..5000].to_vec()).unwrap();
+        for (i, &code) in codes[5000..8000].iter().enumerate() {
+            assert_eq!(index.insert(code).unwrap(), 5000 + i as u32);
+        }
+        assert_eq!(
+            index.extend(codes[8000..].iter().cloned()).unwrap(),
+            8000..10000
+        );
+        assert_eq!(index.codes(), &codes[..]);
+        assert_eq!(
+            index,
+            Index::with_blocks(codes, index.num_blocks()).unwrap()
+        );
+
+        do_range_search_on(&index);
+        do_topk_search_on(&index);
+    }
+
+    #[test]
+    fn insert_fails() {
+        let mut index = Index::with_dimensions(vec![0u64, 1], 48).unwrap();
+        assert!(index.insert(1 << 48).is_err());
+        assert!(index.extend(vec![1, 1 << 48]).is_err());
+        assert_eq!(index.codes(), &[0, 1]);
+        assert_eq!(index.insert(1 << 47).unwrap(), 2);
+    }
+
+    /// Checks the searches against the exhaustive ones on the codes that have not been removed.
+    fn do_search_on_live(index: &Index<u64>) {
+        let ids: Vec<u32> = (0..index.codes().len() as u32)
+            .filter(|&id| !index.is_removed(id))
+            .collect();
+        let codes: Vec<u64> = ids.iter().map(|&id| index.codes()[id as usize]).collect();
+        assert_eq!(index.num_live_codes(), codes.len());
+
+        let mut range_searcher = index.range_searcher();
+        let mut topk_searcher = index.topk_searcher();
+        for qi in (0..index.codes().len()).step_by(100) {
+            let qcode = index.codes()[qi];
+            for rad in 0..6 {
+                let ans1: Vec<u32> = ls::range_search(&codes, qcode, rad)
+                    .iter()
+                    .map(|&i| ids[i as usize])
+                    .collect();
+                let ans2 = range_searcher.run(qcode, rad);
+                assert_eq!(ans1, ans2);
+            }
+            let mut dists = ls::exhaustive_search(&codes, qcode);
+            dists.sort_by_key(|x| x.1);
+            for topk in [1, 10, 100] {
+                let ans = topk_searcher.run(qcode, topk);
+                assert_eq!(ans.len(), topk);
+                for (i, &id) in ans.iter().enumerate() {
+                    assert!(!index.is_removed(id));
+                    assert_eq!(
+                        crate::hamdist(qcode, index.codes()[id as usize]),
+                        dists[i].1 as usize
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn remove_works() {
+        let mut rng = thread_rng();
+        let codes = gen_random_codes::<u64>(10000);
+        let mut index = Index::new(codes.clone()).unwrap();
+        for id in 0..10000 {
+            if rng.gen_range(0..3) == 0 {
+                index.remove(id).unwrap();
+            }
+        }
+        do_search_on_live(&index);
+
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+        let other = Index::<u64>::deserialize_from(&data[..]).unwrap();
+        assert_eq!(index, other);
+        assert_eq!(index.num_live_codes(), other.num_live_codes());
+
+        assert!(index.remove(10000).is_err());
+        let id = (0..10000).find(|&id| index.is_removed(id)).unwrap();
+        assert!(index.remove(id).is_err());
+    }
+
+    #[test]
+    fn remove_all_works() {
+        let mut index = Index::with_blocks(vec![0b0000u8, 0b1111, 0b0111], 2).unwrap();
+        for id in 0..3 {
+            index.remove(id).unwrap();
+        }
+        assert!(index.range_searcher().run(0, 4).is_empty());
+        assert!(index.topk_searcher().run(0, 3).is_empty());
+        assert_eq!(index.insert(0b0011).unwrap(), 3);
+        assert_eq!(index.topk_searcher().run(0, 3), &[3]);
+    }
+
+    #[test]
+    fn compact_works() {
+        let mut rng = thread_rng();
+        let codes = gen_random_codes::<u64>(10000);
+        let mut index = Index::new(codes.clone()).unwrap();
+        for id in 0..10000 {
+            if rng.gen_range(0..3) == 0 {
+                index.remove(id).unwrap();
+            }
+        }
+
+        let mut compacted = index.clone();
+        let map = compacted.compact(false);
+        assert_eq!(compacted.codes(), &codes[..]);
+        for (id, &new_id) in map.iter().enumerate() {
+            assert_eq!(new_id.is_none(), index.is_removed(id as u32));
+            assert!(new_id.is_none() || new_id == Some(id as u32));
+        }
+        do_search_on_live(&compacted);
+
+        let map = index.compact(true);
+        let live: Vec<u64> = (0..10000)
+            .filter(|&id| map[id].is_some())
+            .map(|id| codes[id])
+            .collect();
+        assert_eq!(index.codes(), &live[..]);
+        assert_eq!(index.num_live_codes(), live.len());
+        for (id, &new_id) in map.iter().enumerate() {
+            if let Some(new_id) = new_id {
+                assert_eq!(index.codes()[new_id as usize], codes[id]);
+            }
+        }
+        assert_eq!(index, Index::with_blocks(live, index.num_blocks()).unwrap());
+        do_search_on_live(&index);
+    }
+
+    #[test]
+    fn update_works() {
+        let mut rng = thread_rng();
+        let mut codes = gen_random_codes::<u64>(10000);
+        let mut index = Index::new(codes.clone()).unwrap();
+        let new_codes = gen_random_codes::<u64>(3000);
+        for code in new_codes {
+            let id = rng.gen_range(0..10000);
+            index.update(id, code).unwrap();
+            codes[id as usize] = code;
+        }
+        // Moves some codes to their neighbors
+        for id in (0..10000).step_by(7) {
+            let code = codes[id] ^ (1 << rng.gen_range(0..64));
+            index.update(id as u32, code).unwrap();
+            codes[id] = code;
+        }
+        assert_eq!(index.codes(), &codes[..]);
+        assert_eq!(
+            index,
+            Index::with_blocks(codes, index.num_blocks()).unwrap()
+        );
+
+        do_range_search_on(&index);
+        do_topk_search_on(&index);
+    }
+
+    #[test]
+    fn update_fails() {
+        let mut index = Index::with_dimensions(vec![0u64, 1, 2], 48).unwrap();
+        assert!(index.update(3, 0).is_err());
+        assert!(index.update(0, 1 << 48).is_err());
+        index.remove(1).unwrap();
+        assert!(index.update(1, 0).is_err());
+        index.update(2, 1 << 47).unwrap();
+        assert_eq!(index.codes(), &[0, 1, 1 << 47]);
+    }
+
+    #[test]
+    fn merge_works() {
+        let codes = gen_random_codes::<u64>(10000);
+        let a = Index::with_blocks(codes[..6000].to_vec(), 5).unwrap();
+        let mut b = Index::with_blocks(codes[6000..].to_vec(), 5).unwrap();
+        b.remove(10).unwrap();
+
+        let index = Index::merge(a, b).unwrap();
+        assert_eq!(index.codes(), &codes[..]);
+        assert!(index.is_removed(6010));
+        assert_eq!(index.num_live_codes(), 9999);
+
+        let mut expected = Index::with_blocks(codes, 5).unwrap();
+        expected.remove(6010).unwrap();
+        assert_eq!(index, expected);
+        do_search_on_live(&index);
+    }
+
+    #[test]
+    fn merge_repartitioned_works() {
+        let codes = gen_random_codes::<u64>(10000);
+        let a = Index::with_blocks(codes[..6000].to_vec(), 5).unwrap();
+        let b = IndexBuilder::new()
+            .num_blocks(6)
+            .permutation(BitPermutation::Random(7))
+            .build(codes[6000..].to_vec())
+            .unwrap();
+        let mut b2 = b.clone();
+        b2.remove(3).unwrap();
+
+        assert!(Index::merge(a.clone(), b.clone()).is_err());
+        let index = Index::merge_repartitioned(a.clone(), b).unwrap();
+        assert_eq!(index, Index::with_blocks(codes.clone(), 5).unwrap());
+
+        let index = Index::merge_repartitioned(a, b2).unwrap();
+        assert_eq!(index.num_blocks(), 5);
+        assert_eq!(index.codes(), &codes[..]);
+        assert!(index.is_removed(6003));
+        assert_eq!(index.num_live_codes(), 9999);
+        do_search_on_live(&index);
+
+        let a = Index::with_dimensions(vec![0u64, 1], 48).unwrap();
+        let b = Index::new(vec![0u64, 1 << 48]).unwrap();
+        assert!(Index::merge_repartitioned(a, b).is_err());
+    }
+
+    #[test]
+    fn with_blocks_u128_works() {
+        let codes = gen_random_codes::<u128>(10000);
+        assert!(Index::with_blocks(codes.clone(), 3).is_err());
+
+        let index = Index::with_blocks(codes, 8).unwrap();
+        assert_eq!(index.num_blocks(), 8);
+
+        let mut searcher = index.range_searcher();
+        for qi in (0..10000).step_by(100) {
+            let qcode = index.codes()[qi];
+            let ans1 = ls::range_search(index.codes(), qcode, 10);
+            let ans2 = searcher.run(qcode, 10);
+            assert_eq!(ans1, ans2);
+        }
+    }
 }
@@ -21,7 +21,7 @@ impl SigGenerator64 {
 
     /// Initialize the generator.
     pub fn init(&mut self, base: u64, dim: usize, radius: usize) {
-        debug_assert!(radius < dim);
+        debug_assert!(radius <= dim);
 
         self.sig = 0;
         self.base = base;
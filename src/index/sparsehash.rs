@@ -3,8 +3,12 @@ use anyhow::{anyhow, Result};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
 const GROUP_SIZE: usize = 64;
+#[allow(clippy::legacy_numeric_constants)]
 const COUNT_FLAG: u32 = u32::max_value();
 
+/// The maximum number of bits for a table index.
+pub const MAX_BITS: usize = 32;
+
 /// Sparse hash table of the internal data structure of MIH.
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Table {
@@ -15,8 +19,8 @@ pub struct Table {
 impl Table {
     /// Make a new table accessable with index in [0..2^bits).
     pub fn new(num_bits: usize) -> Result<Self> {
-        if num_bits == 0 {
-            return Err(anyhow!("num_bits {} must not be zero", num_bits));
+        if num_bits == 0 || MAX_BITS < num_bits {
+            return Err(anyhow!("num_bits {} must be in [1,{}]", num_bits, MAX_BITS));
         }
 
         let len = 1 << num_bits;
@@ -40,7 +44,6 @@ impl Table {
         self.groups[gpos].access(gmod)
     }
 
-    #[allow(dead_code)]
     #[inline(always)]
     pub fn insert(&mut self, idx: usize, dat: u32) {
         debug_assert!(idx < self.len());
@@ -49,6 +52,15 @@ impl Table {
         self.groups[gpos].insert(gmod, dat);
     }
 
+    /// Removes `dat` from the bucket of `idx`, returning `false` if it is not found.
+    #[inline(always)]
+    pub fn remove(&mut self, idx: usize, dat: u32) -> bool {
+        debug_assert!(idx < self.len());
+        let gpos = idx / GROUP_SIZE;
+        let gmod = idx % GROUP_SIZE;
+        self.groups[gpos].remove(gmod, dat)
+    }
+
     #[inline(always)]
     pub fn count_insert(&mut self, idx: usize) {
         debug_assert!(idx < self.len());
@@ -65,6 +77,25 @@ impl Table {
         self.groups[gpos].data_insert(gmod, dat);
     }
 
+    /// Appends the data of `other` shifted by `offset` to the buckets of the same indices.
+    pub fn merge(&mut self, other: &Self, offset: u32) {
+        debug_assert_eq!(self.num_bits, other.num_bits);
+        for (g, o) in self.groups.iter_mut().zip(other.groups.iter()) {
+            g.merge(o, offset);
+        }
+    }
+
+    /// Keeps only the data for which `f` returns `Some`, replacing them with the returned values.
+    /// The buckets that become empty are released.
+    pub fn retain_map<F>(&mut self, mut f: F)
+    where
+        F: FnMut(u32) -> Option<u32>,
+    {
+        for g in &mut self.groups {
+            g.retain_map(&mut f);
+        }
+    }
+
     #[inline(always)]
     pub const fn len(&self) -> usize {
         1 << self.num_bits
@@ -148,8 +179,11 @@ impl Group {
             self.bitmap = set(self.bitmap, idx);
         }
 
+        // Keeps the data in each bucket sorted
         let totones = popcnt(self.bitmap);
-        let position = totones + 1 + self.array[howmany + 1] as usize;
+        let bpos = totones + 1 + self.array[howmany] as usize;
+        let epos = totones + 1 + self.array[howmany + 1] as usize;
+        let position = bpos + self.array[bpos..epos].partition_point(|&x| x <= dat);
         self.array.insert(position, dat);
 
         for i in howmany + 1..totones + 1 {
@@ -157,6 +191,39 @@ impl Group {
         }
     }
 
+    #[inline(always)]
+    fn remove(&mut self, idx: usize, dat: u32) -> bool {
+        debug_assert!(idx < GROUP_SIZE);
+
+        if !get(self.bitmap, idx) {
+            return false;
+        }
+
+        let howmany = popcnt_mask(self.bitmap, idx);
+        let totones = popcnt(self.bitmap);
+
+        let bpos = totones + 1 + self.array[howmany] as usize;
+        let epos = totones + 1 + self.array[howmany + 1] as usize;
+        let position = match self.array[bpos..epos].iter().position(|&x| x == dat) {
+            Some(i) => bpos + i,
+            None => return false,
+        };
+        self.array.remove(position);
+
+        for i in howmany + 1..totones + 1 {
+            self.array[i] -= 1;
+        }
+
+        if self.array[howmany] == self.array[howmany + 1] {
+            self.array.remove(howmany + 1);
+            self.bitmap = unset(self.bitmap, idx);
+            if self.bitmap == 0 {
+                self.array = vec![];
+            }
+        }
+        true
+    }
+
     #[inline(always)]
     fn count_insert(&mut self, idx: usize) {
         debug_assert!(idx < GROUP_SIZE);
@@ -213,6 +280,65 @@ impl Group {
         }
     }
 
+    fn merge(&mut self, other: &Self, offset: u32) {
+        if other.bitmap == 0 {
+            return;
+        }
+
+        let bitmap = self.bitmap | other.bitmap;
+        let mut array = vec![0];
+        let mut data = vec![];
+
+        for idx in (0..GROUP_SIZE).filter(|&idx| get(bitmap, idx)) {
+            if let Some(a) = self.access(idx) {
+                data.extend_from_slice(a);
+            }
+            if let Some(a) = other.access(idx) {
+                data.extend(a.iter().map(|&dat| dat + offset));
+            }
+            array.push(data.len() as u32);
+        }
+
+        array.extend(data);
+        self.bitmap = bitmap;
+        self.array = array;
+    }
+
+    fn retain_map<F>(&mut self, f: &mut F)
+    where
+        F: FnMut(u32) -> Option<u32>,
+    {
+        if self.bitmap == 0 {
+            return;
+        }
+
+        let totones = popcnt(self.bitmap);
+        let mut bitmap = 0;
+        let mut array = vec![0];
+        let mut data = vec![];
+
+        for (howmany, idx) in (0..GROUP_SIZE)
+            .filter(|&idx| get(self.bitmap, idx))
+            .enumerate()
+        {
+            let bpos = totones + 1 + self.array[howmany] as usize;
+            let epos = totones + 1 + self.array[howmany + 1] as usize;
+            data.extend(self.array[bpos..epos].iter().filter_map(|&dat| f(dat)));
+            if array[array.len() - 1] as usize != data.len() {
+                bitmap = set(bitmap, idx);
+                array.push(data.len() as u32);
+            }
+        }
+
+        self.bitmap = bitmap;
+        if bitmap == 0 {
+            self.array = vec![];
+        } else {
+            array.extend(data);
+            self.array = array;
+        }
+    }
+
     #[inline(always)]
     fn len(&self, idx: usize) -> usize {
         debug_assert!(idx < GROUP_SIZE);
@@ -271,7 +397,14 @@ fn set(x: u64, i: usize) -> u64 {
     x | (1 << i)
 }
 
+#[inline(always)]
+fn unset(x: u64, i: usize) -> u64 {
+    debug_assert!(i < 64);
+    x & !(1 << i)
+}
+
 #[cfg(test)]
+#[allow(clippy::needless_range_loop, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
     use rand::prelude::*;
@@ -403,6 +536,123 @@ mod tests {
         }
     }
 
+    #[test]
+    fn group_retain_map_works() {
+        let mut rng = thread_rng();
+
+        let mut obj1 = vec![Vec::<u32>::default(); GROUP_SIZE];
+        let mut obj2 = Group::default();
+
+        for i in 0..100 {
+            let idx = rng.gen_range(0..GROUP_SIZE);
+            obj1[idx].push(i);
+            obj2.insert(idx, i);
+        }
+
+        // Drops the odd values and halves the even ones
+        let f = |dat: u32| {
+            if dat.is_multiple_of(2) {
+                Some(dat / 2)
+            } else {
+                None
+            }
+        };
+        obj2.retain_map(&mut { f });
+
+        let mut obj3 = Group::default();
+        for (idx, org) in obj1.iter().enumerate() {
+            let org: Vec<u32> = org.iter().filter_map(|&dat| f(dat)).collect();
+            match obj2.access(idx) {
+                None => assert!(org.is_empty()),
+                Some(a) => assert_eq!(&org[..], a),
+            }
+            for dat in org {
+                obj3.insert(idx, dat);
+            }
+        }
+        assert_eq!(obj2, obj3);
+
+        obj2.retain_map(&mut |_| None);
+        assert_eq!(obj2, Group::default());
+    }
+
+    #[test]
+    fn group_remove_works() {
+        let mut rng = thread_rng();
+
+        let mut obj1 = vec![Vec::<u32>::default(); GROUP_SIZE];
+        let mut obj2 = Group::default();
+
+        for i in 0..100 {
+            let idx = rng.gen_range(0..GROUP_SIZE);
+            obj1[idx].push(i);
+            obj2.insert(idx, i);
+        }
+
+        for i in (0..100).rev().step_by(3) {
+            let idx = obj1.iter().position(|org| org.contains(&i)).unwrap();
+            obj1[idx].retain(|&x| x != i);
+            assert!(obj2.remove(idx, i));
+            assert!(!obj2.remove(idx, i));
+        }
+
+        // Re-inserts some of the removed data into the middle of the buckets
+        for i in (0..100).rev().step_by(6) {
+            let idx = rng.gen_range(0..GROUP_SIZE);
+            let pos = obj1[idx].partition_point(|&x| x <= i);
+            obj1[idx].insert(pos, i);
+            obj2.insert(idx, i);
+        }
+
+        let mut obj3 = Group::default();
+        for (idx, org) in obj1.iter().enumerate() {
+            match obj2.access(idx) {
+                None => assert!(org.is_empty()),
+                Some(a) => assert_eq!(&org[..], a),
+            }
+            for &dat in org {
+                obj3.insert(idx, dat);
+            }
+        }
+        assert_eq!(obj2, obj3);
+
+        for (idx, org) in obj1.iter().enumerate() {
+            for &dat in org {
+                assert!(obj2.remove(idx, dat));
+            }
+        }
+        assert_eq!(obj2, Group::default());
+    }
+
+    #[test]
+    fn group_merge_works() {
+        let mut rng = thread_rng();
+
+        let mut obj1 = Group::default();
+        let mut obj2 = Group::default();
+        let mut obj3 = Group::default();
+
+        for i in 0..200 {
+            let idx = rng.gen_range(0..GROUP_SIZE / 2) * if i < 100 { 1 } else { 2 };
+            if i < 100 {
+                obj1.insert(idx, i);
+            } else {
+                obj2.insert(idx, i - 100);
+            }
+            obj3.insert(idx, i);
+        }
+
+        obj1.merge(&obj2, 100);
+        assert_eq!(obj1, obj3);
+
+        obj1.merge(&Group::default(), 100);
+        assert_eq!(obj1, obj3);
+
+        let mut obj4 = Group::default();
+        obj4.merge(&obj3, 0);
+        assert_eq!(obj4, obj3);
+    }
+
     #[test]
     fn group_io_works() {
         let mut rng = thread_rng();
@@ -0,0 +1,453 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Result};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::index::{RangeSearcher, TopkSearcher};
+use crate::{CodeInt, Index, IndexBuilder};
+
+/// Generic trait of user keys stored in [`KeyedIndex`].
+///
+/// It is implemented for the integers of 16 bits or more, `String`, and `Vec<u8>`,
+/// and can be implemented for any hashable type that can be serialized.
+pub trait Key: Clone + Eq + Hash {
+    fn serialize_into<W: Write>(&self, writer: W) -> Result<()>;
+    fn deserialize_from<R: Read>(reader: R) -> Result<Self>;
+}
+
+macro_rules! impl_key_for_int {
+    ($ty:ty, $write:ident, $read:ident) => {
+        impl Key for $ty {
+            fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
+                writer.$write::<LittleEndian>(*self)?;
+                Ok(())
+            }
+
+            fn deserialize_from<R: Read>(mut reader: R) -> Result<Self> {
+                Ok(reader.$read::<LittleEndian>()?)
+            }
+        }
+    };
+}
+
+impl_key_for_int!(u16, write_u16, read_u16);
+impl_key_for_int!(u32, write_u32, read_u32);
+impl_key_for_int!(u64, write_u64, read_u64);
+impl_key_for_int!(u128, write_u128, read_u128);
+impl_key_for_int!(i16, write_i16, read_i16);
+impl_key_for_int!(i32, write_i32, read_i32);
+impl_key_for_int!(i64, write_i64, read_i64);
+impl_key_for_int!(i128, write_i128, read_i128);
+
+impl Key for Vec<u8> {
+    fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
+        writer.write_u64::<LittleEndian>(self.len() as u64)?;
+        writer.write_all(self)?;
+        Ok(())
+    }
+
+    fn deserialize_from<R: Read>(mut reader: R) -> Result<Self> {
+        let len = reader.read_u64::<LittleEndian>()? as usize;
+        let mut bytes = vec![0; len];
+        reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl Key for String {
+    fn serialize_into<W: Write>(&self, writer: W) -> Result<()> {
+        self.as_bytes().to_vec().serialize_into(writer)
+    }
+
+    fn deserialize_from<R: Read>(reader: R) -> Result<Self> {
+        Ok(String::from_utf8(Vec::<u8>::deserialize_from(reader)?)?)
+    }
+}
+
+/// [`Index`] of binary codes identified by user keys instead of positional ids.
+///
+/// The index stores the key of each code, so searches return keys,
+/// codes can be looked up by keys, and the keys are persisted with the index.
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::KeyedIndex;
+///
+/// let entries: Vec<(String, u8)> = vec![
+///     ("a".to_string(), 0b0000),
+///     ("b".to_string(), 0b1111),
+///     ("c".to_string(), 0b0111),
+/// ];
+/// let mut index = KeyedIndex::with_blocks(entries, 2).unwrap();
+///
+/// index.insert("d".to_string(), 0b0001).unwrap();
+/// assert_eq!(index.get(&"c".to_string()), Some(&0b0111));
+///
+/// let mut searcher = index.range_searcher();
+/// assert_eq!(searcher.run(0b0011, 1), vec!["c", "d"]);
+///
+/// index.remove(&"c".to_string()).unwrap();
+/// let mut searcher = index.topk_searcher();
+/// assert_eq!(searcher.run(0b0011, 2), vec!["d", "a"]);
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct KeyedIndex<K: Key, T: CodeInt> {
+    index: Index<T>,
+    keys: Vec<K>,
+    ids: HashMap<K, u32>,
+}
+
+/// Range searcher created by [`KeyedIndex::range_searcher()`].
+pub struct KeyedRangeSearcher<'a, K: Key, T: CodeInt> {
+    keys: &'a [K],
+    searcher: RangeSearcher<'a, T>,
+    answers: Vec<&'a K>,
+}
+
+/// Top-K searcher created by [`KeyedIndex::topk_searcher()`].
+pub struct KeyedTopkSearcher<'a, K: Key, T: CodeInt> {
+    keys: &'a [K],
+    searcher: TopkSearcher<'a, T>,
+    answers: Vec<&'a K>,
+}
+
+impl<K: Key, T: CodeInt> KeyedIndex<K, T> {
+    /// Builds an index from pairs of keys and binary codes in the same manner as [`Index::new()`].
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the keys are not unique, or
+    ///  - [`Index::new()`] fails.
+    pub fn new(entries: Vec<(K, T)>) -> Result<Self> {
+        Self::with_builder(entries, &IndexBuilder::new())
+    }
+
+    /// Builds an index from pairs of keys and binary codes in the same manner as [`Index::with_blocks()`].
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the keys are not unique, or
+    ///  - [`Index::with_blocks()`] fails.
+    pub fn with_blocks(entries: Vec<(K, T)>, num_blocks: usize) -> Result<Self> {
+        Self::with_builder(entries, &IndexBuilder::new().num_blocks(num_blocks))
+    }
+
+    /// Builds an index from pairs of keys and binary codes with the configuration of a builder.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the keys are not unique, or
+    ///  - [`IndexBuilder::build()`] fails.
+    pub fn with_builder(entries: Vec<(K, T)>, builder: &IndexBuilder) -> Result<Self> {
+        let (keys, codes): (Vec<K>, Vec<T>) = entries.into_iter().unzip();
+        let ids = make_ids(&keys, |_| true)?;
+        Ok(Self {
+            index: builder.build(codes)?,
+            keys,
+            ids,
+        })
+    }
+
+    /// Inserts a binary code with a key.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - `key` already exists, or
+    ///  - [`Index::insert()`] fails.
+    pub fn insert(&mut self, key: K, code: T) -> Result<()> {
+        if self.ids.contains_key(&key) {
+            return Err(anyhow!("The key must not exist in the index"));
+        }
+        let id = self.index.insert(code)?;
+        self.keys.push(key.clone());
+        self.ids.insert(key, id);
+        Ok(())
+    }
+
+    /// Removes the code of a key.
+    ///
+    /// The code is excluded from searches immediately,
+    /// and is physically removed by [`KeyedIndex::compact()`].
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when `key` does not exist.
+    pub fn remove(&mut self, key: &K) -> Result<()> {
+        let id = self
+            .ids
+            .remove(key)
+            .ok_or_else(|| anyhow!("The key must exist in the index"))?;
+        self.index.remove(id)
+    }
+
+    /// Replaces the code of a key with a new one.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - `key` does not exist, or
+    ///  - [`Index::update()`] fails.
+    pub fn update(&mut self, key: &K, code: T) -> Result<()> {
+        let id = *self
+            .ids
+            .get(key)
+            .ok_or_else(|| anyhow!("The key must exist in the index"))?;
+        self.index.update(id, code)
+    }
+
+    /// Gets the code of a key, or `None` if it does not exist.
+    pub fn get(&self, key: &K) -> Option<&T> {
+        self.ids
+            .get(key)
+            .map(|&id| &self.index.codes()[id as usize])
+    }
+
+    /// Checks if a key exists.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.ids.contains_key(key)
+    }
+
+    /// Gets the number of keys.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Checks if there is no key.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    /// Physically removes the codes and keys removed by [`KeyedIndex::remove()`].
+    pub fn compact(&mut self) {
+        let map = self.index.compact(true);
+        let mut iter = map.iter();
+        self.keys.retain(|_| iter.next().unwrap().is_some());
+        for id in self.ids.values_mut() {
+            *id = map[*id as usize].unwrap();
+        }
+    }
+
+    /// Makes a range searcher.
+    pub fn range_searcher(&self) -> KeyedRangeSearcher<'_, K, T> {
+        KeyedRangeSearcher {
+            keys: &self.keys,
+            searcher: self.index.range_searcher(),
+            answers: vec![],
+        }
+    }
+
+    /// Makes a top-K searcher.
+    pub fn topk_searcher(&self) -> KeyedTopkSearcher<'_, K, T> {
+        KeyedTopkSearcher {
+            keys: &self.keys,
+            searcher: self.index.topk_searcher(),
+            answers: vec![],
+        }
+    }
+
+    /// Gets the reference of the underlying index,
+    /// whose `i`-th code has the key [`KeyedIndex::key()`] of `i`.
+    pub const fn index(&self) -> &Index<T> {
+        &self.index
+    }
+
+    /// Gets the key of an id of the underlying index.
+    ///
+    /// # Panics
+    ///
+    /// It will panic if `id` is no less than the number of codes in the underlying index.
+    pub fn key(&self, id: u32) -> &K {
+        &self.keys[id as usize]
+    }
+
+    /// Serializes the index into the file.
+    pub fn serialize_into<W: Write>(&self, mut writer: W) -> Result<()> {
+        self.index.serialize_into(&mut writer)?;
+        writer.write_u64::<LittleEndian>(self.keys.len() as u64)?;
+        for x in &self.keys {
+            x.serialize_into(&mut writer)?;
+        }
+        Ok(())
+    }
+
+    /// Deserializes the index from the file.
+    pub fn deserialize_from<R: Read>(mut reader: R) -> Result<Self> {
+        let index = Index::deserialize_from(&mut reader)?;
+        let keys = {
+            let len = reader.read_u64::<LittleEndian>()? as usize;
+            let mut keys = Vec::with_capacity(len);
+            for _ in 0..len {
+                keys.push(K::deserialize_from(&mut reader)?);
+            }
+            keys
+        };
+        if keys.len() != index.codes().len() {
+            return Err(anyhow!(
+                "The number of keys {} must be equal to that of codes {}",
+                keys.len(),
+                index.codes().len()
+            ));
+        }
+        let ids = make_ids(&keys, |id| !index.is_removed(id))?;
+        Ok(Self { index, keys, ids })
+    }
+}
+
+/// Makes the map from the keys of live ids to the ids.
+fn make_ids<K: Key, F>(keys: &[K], is_live: F) -> Result<HashMap<K, u32>>
+where
+    F: Fn(u32) -> bool,
+{
+    let mut ids = HashMap::with_capacity(keys.len());
+    for (id, key) in keys.iter().enumerate() {
+        let id = id as u32;
+        if is_live(id) && ids.insert(key.clone(), id).is_some() {
+            return Err(anyhow!("The key of the entry {} must be unique", id));
+        }
+    }
+    Ok(ids)
+}
+
+impl<'a, K: Key, T: CodeInt> KeyedRangeSearcher<'a, K, T> {
+    /// Searches the keys of codes whose Hamming distances to a query code are within a query radius,
+    /// in the same manner as [`RangeSearcher::run()`].
+    /// The keys are sorted in the ids of the underlying index.
+    pub fn run(&mut self, qcode: T, radius: usize) -> &[&'a K] {
+        let keys = self.keys;
+        self.answers.clear();
+        self.answers.extend(
+            self.searcher
+                .run(qcode, radius)
+                .iter()
+                .map(|&id| &keys[id as usize]),
+        );
+        &self.answers
+    }
+}
+
+impl<'a, K: Key, T: CodeInt> KeyedTopkSearcher<'a, K, T> {
+    /// Searches the keys of top-K codes that are closest to a query code,
+    /// in the same manner as [`TopkSearcher::run()`].
+    pub fn run(&mut self, qcode: T, topk: usize) -> &[&'a K] {
+        let keys = self.keys;
+        self.answers.clear();
+        self.answers.extend(
+            self.searcher
+                .run(qcode, topk)
+                .iter()
+                .map(|&id| &keys[id as usize]),
+        );
+        &self.answers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn keyed_index_works() {
+        let mut rng = thread_rng();
+        let entries: Vec<(u64, u64)> = (0..10000u64).map(|i| (i * 7, rng.gen())).collect();
+        let mut index = KeyedIndex::new(entries.clone()).unwrap();
+        let mut expected: HashMap<u64, u64> = entries.into_iter().collect();
+
+        for i in 0..1000u64 {
+            let key = rng.gen_range(0..10000) * 7;
+            match i % 3 {
+                0 => {
+                    let code = rng.gen();
+                    let key = key + rng.gen_range(0..2);
+                    assert_eq!(
+                        index.insert(key, code).is_ok(),
+                        !expected.contains_key(&key)
+                    );
+                    expected.entry(key).or_insert(code);
+                }
+                1 => {
+                    let code = rng.gen();
+                    assert_eq!(
+                        index.update(&key, code).is_ok(),
+                        expected.contains_key(&key)
+                    );
+                    expected.entry(key).and_modify(|c| *c = code);
+                }
+                _ => {
+                    assert_eq!(index.remove(&key).is_ok(), expected.remove(&key).is_some());
+                }
+            }
+        }
+        assert_eq!(index.len(), expected.len());
+        for (key, code) in &expected {
+            assert_eq!(index.get(key), Some(code));
+        }
+
+        let check = |index: &KeyedIndex<u64, u64>| {
+            let mut searcher = index.range_searcher();
+            for (&key, &code) in expected.iter().take(100) {
+                let mut answers: Vec<u64> = searcher.run(code, 8).iter().map(|&&k| k).collect();
+                answers.sort_unstable();
+                let mut naive: Vec<u64> = expected
+                    .iter()
+                    .filter(|(_, c)| c.distance(&code) <= 8)
+                    .map(|(&k, _)| k)
+                    .collect();
+                naive.sort_unstable();
+                assert_eq!(answers, naive);
+                assert!(answers.binary_search(&key).is_ok());
+            }
+            let mut searcher = index.topk_searcher();
+            for (&key, &code) in expected.iter().take(100) {
+                assert_eq!(searcher.run(code, 1), vec![&key]);
+            }
+        };
+        check(&index);
+
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+        let other = KeyedIndex::<u64, u64>::deserialize_from(&data[..]).unwrap();
+        assert_eq!(index, other);
+
+        index.compact();
+        assert_eq!(index.index().codes().len(), expected.len());
+        check(&index);
+        for (key, &id) in &index.ids {
+            assert_eq!(index.key(id), key);
+        }
+    }
+
+    #[test]
+    fn duplicate_keys_fail() {
+        let entries = vec![(1u32, 0b00u8), (2, 0b01), (1, 0b11)];
+        assert!(KeyedIndex::with_blocks(entries, 2).is_err());
+    }
+
+    #[test]
+    fn string_keys_are_serialized() {
+        let entries = vec![("x".to_string(), 0b00u8), ("yz".to_string(), 0b11)];
+        let mut index = KeyedIndex::with_blocks(entries, 2).unwrap();
+        index.remove(&"x".to_string()).unwrap();
+        index.insert("x".to_string(), 0b01).unwrap();
+
+        let mut data = vec![];
+        index.serialize_into(&mut data).unwrap();
+        let other = KeyedIndex::<String, u8>::deserialize_from(&data[..]).unwrap();
+        assert_eq!(other.get(&"x".to_string()), Some(&0b01));
+        assert_eq!(other.topk_searcher().run(0b00, 1), vec!["x"]);
+    }
+}
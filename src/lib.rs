@@ -14,6 +14,18 @@
 //!
 //! - **Parameter free:** `mih-rs` automatically sets an optimal parameter of MIH depending on a given database (although you can also set this manually).
 //!
+//! - **Incremental updates:** Codes can be inserted, updated, and removed in a built index without rebuilding it. For a continuously growing database, `mih_rs::SegmentedIndex` buffers insertions and merges them into segments of `mih_rs::Index`.
+//!
+//! - **User keys:** `mih_rs::KeyedIndex` stores a user key (e.g., `u64` or `String`) with each code, returns keys from searches, and serializes them with the index.
+//!
+//! - **Sharding:** `mih_rs::ShardedIndex` splits a large database into shards built in parallel, and merges their search results exactly.
+//!
+//! - **Sliding windows:** `mih_rs::WindowIndex` keeps the codes of the last N time units or the last M items of a stream, and reclaims expired codes incrementally without rebuilding.
+//!
+//! - **Concurrent reads:** `mih_rs::SnapshotIndex` publishes immutable versions of an index, so searches on a snapshot never block on insertions, updates, and removals.
+//!
+//! - **Durability:** `mih_rs::DurableIndex` logs insertions, updates, and removals to a write-ahead log with per-record checksums, and recovers from crashes by replaying the log on the last snapshot.
+//!
 //! - **Serialization:** `mih-rs` supports to serialize/deserialize the index.
 //!
 //! ## Example
@@ -59,22 +71,65 @@
 //! ## Binary code types
 //!
 //! `mih_rs::Index` can be built from a vector of type `mih_rs::CodeInt`
-//! that is a primitive integer trait supporting a popcount operation.
-//! Currently, this library defines `mih_rs::CodeInt` for `u8`, `u16`, `u32`, and `u64`.
+//! that is a trait of binary codes supporting bit extraction, Hamming distance, and serialization
+//! (and can be implemented for user-defined code types).
+//! Currently, this library defines `mih_rs::CodeInt` for `u8`, `u16`, `u32`, `u64`, and `u128`.
+//!
+//! Codes longer than 128 bits, such as 256-bit ORB/BRIEF descriptors, can be represented by
+//! `mih_rs::WideCode<W>` that is an array of `W` 64-bit words (i.e., `64 * W` bits).
+//! Byte-array digests (e.g., `[u8; 32]`) can be converted into `mih_rs::WideCode<W>` with a fixed bit order,
+//! and `mih_rs::Index::from_bytes` builds an index from them directly.
+//!
+//! If the code length is given at runtime (e.g., from a config file),
+//! `mih_rs::DynCode` represents a code by a boxed slice of `u64` words, and
+//! `mih_rs::DynIndex` (i.e., `mih_rs::Index<DynCode>`) can be built from codes packed in a flat vector of the words.
 
 /// An implementation of multi-index hashing.
 pub mod index;
 
+/// An implementation of multi-index hashing for binary codes whose length is given at runtime.
+pub mod dynindex;
+
+/// A log-structured index of multi-index hashing segments for a growing database.
+pub mod segmented;
+
+/// A sharded index of multi-index hashing for large databases.
+pub mod sharded;
+
+/// An index of binary codes identified by user keys.
+pub mod keyed;
+
+/// An index shared by concurrent readers and writers through immutable versions.
+pub mod snapshot;
+
+/// An index over a sliding window of a stream, expiring old codes incrementally.
+pub mod window;
+
+/// A crash-safe index of multi-index hashing backed by a write-ahead log.
+pub mod wal;
+
 /// Exhaustive search functions (for benchmark).
 pub mod ls;
 
 /// A generic trait of supported binary codes.
 pub mod codeint;
 
-pub use codeint::CodeInt;
-pub use index::Index;
+mod popcnt;
+
+pub use codeint::{CodeInt, DynCode, WideCode};
+pub use dynindex::DynIndex;
+pub use index::{
+    BitPermutation, BlockWidths, Index, IndexBuilder, Neighbor, NeighborIter, NeighborOrder,
+    TiePolicy,
+};
+pub use keyed::{Key, KeyedIndex};
+pub use segmented::SegmentedIndex;
+pub use sharded::{ShardedIndex, Sharding};
+pub use snapshot::SnapshotIndex;
+pub use wal::DurableIndex;
+pub use window::{Retention, WindowIndex};
 
 /// Gets the Hamming distance between two binary codes.
 pub fn hamdist<T: CodeInt>(x: T, y: T) -> usize {
-    (x ^ y).popcnt() as usize
+    x.distance(&y)
 }
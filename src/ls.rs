@@ -1,6 +1,8 @@
-use crate::hamdist;
 use crate::CodeInt;
 
+/// The number of codes whose distances are computed at once.
+const BATCH_SIZE: usize = 256;
+
 /// Finds the neighbors in codes, whose Hamming distances to qcode are within radius.
 /// Returns the ids of the neighbor codes.
 pub fn range_search<T: CodeInt>(codes: &[T], qcode: T, radius: usize) -> Vec<u32> {
@@ -18,10 +20,14 @@ pub fn range_search_with_buf<T: CodeInt>(
     answers: &mut Vec<u32>,
 ) {
     answers.clear();
-    for (i, &code) in codes.iter().enumerate() {
-        let dist = hamdist(code, qcode);
-        if dist <= radius {
-            answers.push(i as u32);
+    let mut dists = [0; BATCH_SIZE];
+    for (b, chunk) in codes.chunks(BATCH_SIZE).enumerate() {
+        let dists = &mut dists[..chunk.len()];
+        T::distances(chunk, &qcode, dists);
+        for (i, &dist) in dists.iter().enumerate() {
+            if dist as usize <= radius {
+                answers.push((b * BATCH_SIZE + i) as u32);
+            }
         }
     }
 }
@@ -42,8 +48,13 @@ pub fn exhaustive_search_with_buf<T: CodeInt>(
     answers: &mut Vec<(u32, u32)>,
 ) {
     answers.resize(codes.len(), Default::default());
-    for i in 0..codes.len() {
-        let dist = hamdist(codes[i], qcode);
-        answers[i] = (i as u32, dist as u32);
+    let mut dists = [0; BATCH_SIZE];
+    for (b, chunk) in codes.chunks(BATCH_SIZE).enumerate() {
+        let dists = &mut dists[..chunk.len()];
+        T::distances(chunk, &qcode, dists);
+        for (i, &dist) in dists.iter().enumerate() {
+            let id = b * BATCH_SIZE + i;
+            answers[id] = (id as u32, dist);
+        }
     }
 }
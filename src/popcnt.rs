@@ -0,0 +1,300 @@
+use std::sync::OnceLock;
+
+/// Instruction set used for popcount, detected at runtime.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Level {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Popcnt,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+    #[cfg(target_arch = "x86_64")]
+    Avx512,
+}
+
+/// Gets the best instruction set available on the running CPU.
+#[inline(always)]
+pub(crate) fn level() -> Level {
+    static LEVEL: OnceLock<Level> = OnceLock::new();
+    *LEVEL.get_or_init(detect)
+}
+
+fn detect() -> Level {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx512f")
+            && is_x86_feature_detected!("avx512vpopcntdq")
+            && is_x86_feature_detected!("popcnt")
+        {
+            return Level::Avx512;
+        }
+        if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("popcnt") {
+            return Level::Avx2;
+        }
+        if is_x86_feature_detected!("popcnt") {
+            return Level::Popcnt;
+        }
+    }
+    Level::Scalar
+}
+
+/// Computes the popcount of `x ^ y`, where `x` and `y` have the same length.
+///
+/// # Panics
+///
+/// It will panic if the lengths of `x` and `y` are different.
+#[inline(always)]
+pub(crate) fn xor_popcnt(x: &[u64], y: &[u64]) -> usize {
+    xor_popcnt_with(level(), x, y)
+}
+
+/// Computes the popcounts of `code ^ qcode` for each code of `qcode.len()` words packed in `codes`.
+/// The results are stored in `dists`.
+///
+/// # Panics
+///
+/// It will panic if the length of `codes` is not `qcode.len() * dists.len()`.
+#[inline(always)]
+pub(crate) fn xor_popcnt_batch(codes: &[u64], qcode: &[u64], dists: &mut [u32]) {
+    xor_popcnt_batch_with(level(), codes, qcode, dists)
+}
+
+#[inline(always)]
+fn xor_popcnt_with(level: Level, x: &[u64], y: &[u64]) -> usize {
+    // The kernels access the slices through raw pointers, so the lengths are checked even in release builds.
+    assert_eq!(x.len(), y.len());
+    // SAFETY: The instruction sets are checked by `level()`.
+    match level {
+        Level::Scalar => scalar::xor_popcnt(x, y),
+        #[cfg(target_arch = "x86_64")]
+        Level::Popcnt => unsafe { native::xor_popcnt(x, y) },
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx2 => unsafe { avx2::xor_popcnt(x, y) },
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx512 => unsafe { avx512::xor_popcnt(x, y) },
+    }
+}
+
+#[inline(always)]
+fn xor_popcnt_batch_with(level: Level, codes: &[u64], qcode: &[u64], dists: &mut [u32]) {
+    // The batch kernels also write `dists` through raw pointers.
+    assert_eq!(codes.len(), qcode.len() * dists.len());
+    // SAFETY: The instruction sets are checked by `level()`.
+    match level {
+        Level::Scalar => scalar::xor_popcnt_batch(codes, qcode, dists),
+        #[cfg(target_arch = "x86_64")]
+        Level::Popcnt => unsafe { native::xor_popcnt_batch(codes, qcode, dists) },
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx2 => unsafe { avx2::xor_popcnt_batch(codes, qcode, dists) },
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx512 => unsafe { avx512::xor_popcnt_batch(codes, qcode, dists) },
+    }
+}
+
+mod scalar {
+    #[inline(always)]
+    pub fn xor_popcnt(x: &[u64], y: &[u64]) -> usize {
+        x.iter()
+            .zip(y.iter())
+            .map(|(a, b)| (a ^ b).count_ones() as usize)
+            .sum()
+    }
+
+    pub fn xor_popcnt_batch(codes: &[u64], qcode: &[u64], dists: &mut [u32]) {
+        for (code, dist) in codes.chunks_exact(qcode.len()).zip(dists.iter_mut()) {
+            *dist = xor_popcnt(code, qcode) as u32;
+        }
+    }
+}
+
+/// Scalar loops compiled with the `popcnt` instruction.
+#[cfg(target_arch = "x86_64")]
+mod native {
+    #[inline]
+    #[target_feature(enable = "popcnt")]
+    pub unsafe fn xor_popcnt(x: &[u64], y: &[u64]) -> usize {
+        super::scalar::xor_popcnt(x, y)
+    }
+
+    #[target_feature(enable = "popcnt")]
+    pub unsafe fn xor_popcnt_batch(codes: &[u64], qcode: &[u64], dists: &mut [u32]) {
+        super::scalar::xor_popcnt_batch(codes, qcode, dists)
+    }
+}
+
+/// Vectorized popcount with the nibble lookup of `vpshufb` (Mula et al.).
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    /// Counts the bits in each 64-bit lane.
+    #[inline]
+    #[target_feature(enable = "avx2")]
+    unsafe fn popcnt_lanes(v: __m256i) -> __m256i {
+        let lookup = _mm256_setr_epi8(
+            0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4, //
+            0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+        );
+        let low_mask = _mm256_set1_epi8(0x0f);
+        let lo = _mm256_and_si256(v, low_mask);
+        let hi = _mm256_and_si256(_mm256_srli_epi16(v, 4), low_mask);
+        let cnt = _mm256_add_epi8(
+            _mm256_shuffle_epi8(lookup, lo),
+            _mm256_shuffle_epi8(lookup, hi),
+        );
+        _mm256_sad_epu8(cnt, _mm256_setzero_si256())
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx2,popcnt")]
+    pub unsafe fn xor_popcnt(x: &[u64], y: &[u64]) -> usize {
+        let n = x.len() / 4 * 4;
+        let mut acc = _mm256_setzero_si256();
+        for i in (0..n).step_by(4) {
+            let a = _mm256_loadu_si256(x.as_ptr().add(i) as *const __m256i);
+            let b = _mm256_loadu_si256(y.as_ptr().add(i) as *const __m256i);
+            acc = _mm256_add_epi64(acc, popcnt_lanes(_mm256_xor_si256(a, b)));
+        }
+        let mut lanes = [0u64; 4];
+        _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, acc);
+        let mut cnt = lanes.iter().sum::<u64>() as usize;
+        for i in n..x.len() {
+            cnt += (x[i] ^ y[i]).count_ones() as usize;
+        }
+        cnt
+    }
+
+    #[target_feature(enable = "avx2,popcnt")]
+    pub unsafe fn xor_popcnt_batch(codes: &[u64], qcode: &[u64], dists: &mut [u32]) {
+        if qcode.len() != 1 {
+            for (code, dist) in codes.chunks_exact(qcode.len()).zip(dists.iter_mut()) {
+                *dist = xor_popcnt(code, qcode) as u32;
+            }
+            return;
+        }
+
+        // Four single-word codes at once
+        let n = codes.len() / 4 * 4;
+        let q = _mm256_set1_epi64x(qcode[0] as i64);
+        let mut lanes = [0u64; 4];
+        for i in (0..n).step_by(4) {
+            let a = _mm256_loadu_si256(codes.as_ptr().add(i) as *const __m256i);
+            let c = popcnt_lanes(_mm256_xor_si256(a, q));
+            _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, c);
+            for j in 0..4 {
+                dists[i + j] = lanes[j] as u32;
+            }
+        }
+        for i in n..codes.len() {
+            dists[i] = (codes[i] ^ qcode[0]).count_ones();
+        }
+    }
+}
+
+/// Vectorized popcount with the native `vpopcntq` of AVX-512 VPOPCNTDQ.
+#[cfg(target_arch = "x86_64")]
+mod avx512 {
+    use std::arch::x86_64::*;
+
+    #[inline]
+    #[target_feature(enable = "avx512f,avx512vpopcntdq,popcnt")]
+    pub unsafe fn xor_popcnt(x: &[u64], y: &[u64]) -> usize {
+        let n = x.len() / 8 * 8;
+        let mut acc = _mm512_setzero_si512();
+        for i in (0..n).step_by(8) {
+            let a = _mm512_loadu_epi64(x.as_ptr().add(i) as *const i64);
+            let b = _mm512_loadu_epi64(y.as_ptr().add(i) as *const i64);
+            acc = _mm512_add_epi64(acc, _mm512_popcnt_epi64(_mm512_xor_si512(a, b)));
+        }
+        if n < x.len() {
+            let mask = ((1u32 << (x.len() - n)) - 1) as __mmask8;
+            let a = _mm512_maskz_loadu_epi64(mask, x.as_ptr().add(n) as *const i64);
+            let b = _mm512_maskz_loadu_epi64(mask, y.as_ptr().add(n) as *const i64);
+            acc = _mm512_add_epi64(acc, _mm512_popcnt_epi64(_mm512_xor_si512(a, b)));
+        }
+        _mm512_reduce_add_epi64(acc) as usize
+    }
+
+    #[target_feature(enable = "avx512f,avx512vpopcntdq,popcnt")]
+    pub unsafe fn xor_popcnt_batch(codes: &[u64], qcode: &[u64], dists: &mut [u32]) {
+        if qcode.len() != 1 {
+            for (code, dist) in codes.chunks_exact(qcode.len()).zip(dists.iter_mut()) {
+                *dist = xor_popcnt(code, qcode) as u32;
+            }
+            return;
+        }
+
+        // Eight single-word codes at once
+        let n = codes.len() / 8 * 8;
+        let q = _mm512_set1_epi64(qcode[0] as i64);
+        for i in (0..n).step_by(8) {
+            let a = _mm512_loadu_epi64(codes.as_ptr().add(i) as *const i64);
+            let c = _mm512_popcnt_epi64(_mm512_xor_si512(a, q));
+            _mm256_storeu_si256(
+                dists.as_mut_ptr().add(i) as *mut __m256i,
+                _mm512_cvtepi64_epi32(c),
+            );
+        }
+        for i in n..codes.len() {
+            dists[i] = (codes[i] ^ qcode[0]).count_ones();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{thread_rng, Rng};
+
+    fn available_levels() -> Vec<Level> {
+        let mut levels = vec![Level::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("popcnt") {
+                levels.push(Level::Popcnt);
+            }
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("popcnt") {
+                levels.push(Level::Avx2);
+            }
+            if is_x86_feature_detected!("avx512f")
+                && is_x86_feature_detected!("avx512vpopcntdq")
+                && is_x86_feature_detected!("popcnt")
+            {
+                levels.push(Level::Avx512);
+            }
+        }
+        levels
+    }
+
+    #[test]
+    fn xor_popcnt_works() {
+        let mut rng = thread_rng();
+        for len in 0..40 {
+            let x: Vec<u64> = (0..len).map(|_| rng.gen()).collect();
+            let y: Vec<u64> = (0..len).map(|_| rng.gen()).collect();
+            let expected = scalar::xor_popcnt(&x, &y);
+            for &level in &available_levels() {
+                assert_eq!(xor_popcnt_with(level, &x, &y), expected, "{:?}", level);
+            }
+        }
+    }
+
+    #[test]
+    fn xor_popcnt_batch_works() {
+        let mut rng = thread_rng();
+        for words in 1..18 {
+            for num_codes in [0, 1, 3, 4, 7, 8, 9, 100] {
+                let codes: Vec<u64> = (0..words * num_codes).map(|_| rng.gen()).collect();
+                let qcode: Vec<u64> = (0..words).map(|_| rng.gen()).collect();
+                let mut expected = vec![0; num_codes];
+                scalar::xor_popcnt_batch(&codes, &qcode, &mut expected);
+                for &level in &available_levels() {
+                    let mut dists = vec![0; num_codes];
+                    xor_popcnt_batch_with(level, &codes, &qcode, &mut dists);
+                    assert_eq!(dists, expected, "{:?}", level);
+                }
+            }
+        }
+    }
+}
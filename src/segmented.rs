@@ -0,0 +1,483 @@
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, Result};
+
+use crate::index::{has_high_bits, RangeSearcher, TopkSearcher};
+use crate::{ls, CodeInt, Index};
+
+/// A segment is merged with the next one if it is less than this times as large.
+const MERGE_FACTOR: usize = 4;
+
+/// Multi-index hashing for a growing database, consisting of immutable segments and a mutable buffer.
+///
+/// [`SegmentedIndex`] is a log-structured index for cheap insertions.
+/// Inserted codes are first buffered in a small *memtable* that is searched linearly,
+/// and the memtable is flushed into a new [`Index`] segment when it fills.
+/// Adjacent segments of similar sizes are merged into a larger one in a background thread,
+/// so the number of segments stays logarithmic in the number of codes.
+///
+/// The codes are identified by global ids assigned in the order of insertion,
+/// and searches merge the results of all the segments and the memtable exactly.
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::SegmentedIndex;
+///
+/// let mut index = SegmentedIndex::<u64>::new(2).unwrap();
+///
+/// index.insert(0b1111).unwrap();
+/// index.insert(0b0111).unwrap();
+/// index.insert(0b0000).unwrap(); // flushes the two codes into a segment
+/// index.insert(0b0011).unwrap();
+/// assert_eq!(index.num_codes(), 4);
+///
+/// let mut searcher = index.range_searcher();
+/// assert_eq!(searcher.run(0b0011, 1), vec![1, 3]);
+///
+/// let mut searcher = index.topk_searcher();
+/// assert_eq!(searcher.run(0b0011, 3), vec![3, 1, 0]);
+/// ```
+pub struct SegmentedIndex<T: CodeInt> {
+    num_dimensions: usize,
+    memtable_capacity: usize,
+    segments: Vec<Segment<T>>,
+    memtable: Vec<T>,
+    memtable_base: u32,
+    merging: Option<Merging<T>>,
+}
+
+/// Immutable index of codes whose global ids start from `base`.
+struct Segment<T: CodeInt> {
+    base: u32,
+    index: Index<T>,
+}
+
+/// Merge of the segments `[beg, end)` running in a background thread.
+struct Merging<T: CodeInt> {
+    beg: usize,
+    end: usize,
+    handle: JoinHandle<Result<Index<T>>>,
+}
+
+/// Range searcher created by [`SegmentedIndex::range_searcher()`].
+pub struct SegmentedRangeSearcher<'a, T: CodeInt> {
+    index: &'a SegmentedIndex<T>,
+    searchers: Vec<RangeSearcher<'a, T>>,
+    answers: Vec<u32>,
+}
+
+/// Top-K searcher created by [`SegmentedIndex::topk_searcher()`].
+pub struct SegmentedTopkSearcher<'a, T: CodeInt> {
+    index: &'a SegmentedIndex<T>,
+    searchers: Vec<TopkSearcher<'a, T>>,
+    candidates: Vec<(usize, u32)>,
+    answers: Vec<u32>,
+}
+
+impl<T> SegmentedIndex<T>
+where
+    T: CodeInt + Send + 'static,
+{
+    /// Creates an empty index whose memtable buffers at most `memtable_capacity` codes.
+    ///
+    /// # Arguments
+    ///
+    /// - `memtable_capacity`: The maximum number of codes in the memtable.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when `memtable_capacity` is zero.
+    pub fn new(memtable_capacity: usize) -> Result<Self> {
+        Self::with_dimensions(T::dimensions(), memtable_capacity)
+    }
+
+    /// Creates an empty index for binary codes whose meaningful bits are only the lowest `num_dimensions` bits
+    /// (see [`Index::with_dimensions()`]).
+    ///
+    /// # Arguments
+    ///
+    /// - `num_dimensions`: The number of meaningful bits in a binary code.
+    /// - `memtable_capacity`: The maximum number of codes in the memtable.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - `num_dimensions` is less than 2 or more than [`CodeInt::dimensions()`], or
+    ///  - `memtable_capacity` is zero.
+    pub fn with_dimensions(num_dimensions: usize, memtable_capacity: usize) -> Result<Self> {
+        if num_dimensions < 2 || T::dimensions() < num_dimensions {
+            return Err(anyhow!(
+                "The number of dimensions {} must be in [2,{}]",
+                num_dimensions,
+                T::dimensions()
+            ));
+        }
+        if memtable_capacity == 0 {
+            return Err(anyhow!("The memtable capacity must not be zero"));
+        }
+        Ok(Self {
+            num_dimensions,
+            memtable_capacity,
+            segments: vec![],
+            memtable: Vec::with_capacity(memtable_capacity),
+            memtable_base: 0,
+            merging: None,
+        })
+    }
+
+    /// Inserts a binary code and returns its global id,
+    /// which is the number of codes inserted before.
+    /// If the memtable is full, it is flushed into a new segment beforehand.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the index already has `u32::MAX` codes,
+    ///  - `code` has a bit set at or above [`SegmentedIndex::num_dimensions()`], or
+    ///  - building a segment fails.
+    pub fn insert(&mut self, code: T) -> Result<u32> {
+        if u32::MAX as usize <= self.num_codes() {
+            return Err(anyhow!(
+                "The number of codes must not be no more than {}.",
+                u32::MAX
+            ));
+        }
+        if has_high_bits(&code, self.num_dimensions) {
+            return Err(anyhow!(
+                "The code {} must not have bits at or above {}",
+                self.num_codes(),
+                self.num_dimensions
+            ));
+        }
+        if self.memtable.len() == self.memtable_capacity {
+            self.flush()?;
+        }
+        self.memtable.push(code);
+        Ok(self.num_codes() as u32 - 1)
+    }
+
+    /// Inserts binary codes in order, returning the range of their global ids.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned in the same cases as [`SegmentedIndex::insert()`].
+    /// The codes before the failed one remain inserted.
+    pub fn extend<I>(&mut self, codes: I) -> Result<std::ops::Range<u32>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let beg = self.num_codes() as u32;
+        for code in codes {
+            self.insert(code)?;
+        }
+        Ok(beg..self.num_codes() as u32)
+    }
+
+    /// Flushes the codes in the memtable into a new segment,
+    /// and starts merging segments in the background if needed.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when building a segment fails.
+    pub fn flush(&mut self) -> Result<()> {
+        if !self.memtable.is_empty() {
+            // The memtable is kept until the segment is built, so a failure loses no codes.
+            let index = Index::with_dimensions(self.memtable.clone(), self.num_dimensions)?;
+            self.segments.push(Segment {
+                base: self.memtable_base,
+                index,
+            });
+            self.memtable_base += self.memtable.len() as u32;
+            self.memtable.clear();
+        }
+        self.maintain(false)
+    }
+
+    /// Waits for all the merges of segments to be finished, including those triggered by finished ones.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when building a segment fails.
+    pub fn wait_merges(&mut self) -> Result<()> {
+        self.maintain(true)
+    }
+
+    /// Installs the finished merge and starts a new one if needed.
+    fn maintain(&mut self, wait: bool) -> Result<()> {
+        loop {
+            if let Some(merging) = self.merging.take() {
+                if !wait && !merging.handle.is_finished() {
+                    self.merging = Some(merging);
+                    return Ok(());
+                }
+                let index = merging
+                    .handle
+                    .join()
+                    .map_err(|_| anyhow!("The merge of segments panicked"))??;
+                let base = self.segments[merging.beg].base;
+                self.segments
+                    .splice(merging.beg..merging.end, [Segment { base, index }]);
+            }
+
+            // Merges the last pair of adjacent segments whose sizes are similar.
+            let Some(beg) = (0..self.segments.len().saturating_sub(1)).rev().find(|&s| {
+                self.segments[s].index.codes().len()
+                    < MERGE_FACTOR * self.segments[s + 1].index.codes().len()
+            }) else {
+                return Ok(());
+            };
+
+            let mut codes = self.segments[beg].index.codes().to_vec();
+            codes.extend_from_slice(self.segments[beg + 1].index.codes());
+            let num_dimensions = self.num_dimensions;
+            let handle = std::thread::spawn(move || Index::with_dimensions(codes, num_dimensions));
+            self.merging = Some(Merging {
+                beg,
+                end: beg + 2,
+                handle,
+            });
+
+            if !wait {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<T: CodeInt> SegmentedIndex<T> {
+    /// Returns a searcher [`SegmentedRangeSearcher`] to find neighbor codes
+    /// whose Hamming distances to a query code are within a query radius.
+    pub fn range_searcher(&self) -> SegmentedRangeSearcher<'_, T> {
+        SegmentedRangeSearcher {
+            index: self,
+            searchers: self
+                .segments
+                .iter()
+                .map(|s| s.index.range_searcher())
+                .collect(),
+            answers: Vec::with_capacity(1 << 10),
+        }
+    }
+
+    /// Returns a searcher [`SegmentedTopkSearcher`] to find top-K codes that are closest to a query code.
+    pub fn topk_searcher(&self) -> SegmentedTopkSearcher<'_, T> {
+        SegmentedTopkSearcher {
+            index: self,
+            searchers: self
+                .segments
+                .iter()
+                .map(|s| s.index.topk_searcher())
+                .collect(),
+            candidates: Vec::with_capacity(1 << 10),
+            answers: Vec::with_capacity(1 << 10),
+        }
+    }
+
+    /// Gets the code of a global id.
+    ///
+    /// # Panics
+    ///
+    /// It will panic if `id` is no less than [`SegmentedIndex::num_codes()`].
+    pub fn code(&self, id: u32) -> &T {
+        if self.memtable_base <= id {
+            return &self.memtable[(id - self.memtable_base) as usize];
+        }
+        let s = self.segments.partition_point(|s| s.base <= id) - 1;
+        let segment = &self.segments[s];
+        &segment.index.codes()[(id - segment.base) as usize]
+    }
+
+    /// Gets the number of codes inserted.
+    pub fn num_codes(&self) -> usize {
+        self.memtable_base as usize + self.memtable.len()
+    }
+
+    /// Gets the number of meaningful bits in a binary code.
+    pub const fn num_dimensions(&self) -> usize {
+        self.num_dimensions
+    }
+
+    /// Gets the number of segments, excluding the memtable.
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Gets the number of codes in the memtable.
+    pub fn memtable_len(&self) -> usize {
+        self.memtable.len()
+    }
+}
+
+impl<'a, T> SegmentedRangeSearcher<'a, T>
+where
+    T: CodeInt,
+{
+    /// Searches neighbor codes whose Hamming distances to a query code are within a query radius.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `radius`: Threshold to be searched.
+    ///
+    /// # Returns
+    ///
+    /// A slice of global ids of codes whose Hamming distances to `qcode` are within `radius`.
+    /// The ids are sorted.
+    /// Note that the values of the slice will be updated in the next [`SegmentedRangeSearcher::run()`].
+    pub fn run(&mut self, qcode: T, radius: usize) -> &[u32] {
+        self.answers.clear();
+        if has_high_bits(&qcode, self.index.num_dimensions) {
+            return &self.answers;
+        }
+        // The segments are in the order of ids, so the answers are sorted.
+        for (segment, searcher) in self.index.segments.iter().zip(self.searchers.iter_mut()) {
+            let answers = searcher.run(qcode.clone(), radius);
+            self.answers
+                .extend(answers.iter().map(|&id| segment.base + id));
+        }
+        let base = self.index.memtable_base;
+        for id in ls::range_search(&self.index.memtable, qcode, radius) {
+            self.answers.push(base + id);
+        }
+        &self.answers
+    }
+}
+
+impl<'a, T> SegmentedTopkSearcher<'a, T>
+where
+    T: CodeInt,
+{
+    /// Searches top-K codes that are closest to a query code.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `topk`: Threshold to be searched.
+    ///
+    /// # Returns
+    ///
+    /// A slice of global ids of the `topk` nearest neighbor codes to `qcode`.
+    /// The ids are sorted in the Hamming distances to `qcode`.
+    /// If `topk` is more than the number of codes, all of them are returned.
+    /// Note that the values of the slice will be updated in the next [`SegmentedTopkSearcher::run()`].
+    pub fn run(&mut self, qcode: T, topk: usize) -> &[u32] {
+        self.candidates.clear();
+        self.answers.clear();
+        if has_high_bits(&qcode, self.index.num_dimensions) {
+            return &self.answers;
+        }
+        for (segment, searcher) in self.index.segments.iter().zip(self.searchers.iter_mut()) {
+            let codes = segment.index.codes();
+            for &id in searcher.run(qcode.clone(), topk) {
+                let dist = qcode.distance(&codes[id as usize]);
+                self.candidates.push((dist, segment.base + id));
+            }
+        }
+        let base = self.index.memtable_base;
+        for (id, dist) in ls::exhaustive_search(&self.index.memtable, qcode) {
+            self.candidates.push((dist as usize, base + id));
+        }
+
+        self.candidates.sort_unstable();
+        self.answers
+            .extend(self.candidates.iter().take(topk).map(|&(_, id)| id));
+        &self.answers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn segmented_search_works() {
+        let mut rng = thread_rng();
+        let codes: Vec<u64> = (0..10000).map(|_| rng.gen()).collect();
+
+        let mut index = SegmentedIndex::<u64>::new(500).unwrap();
+        assert_eq!(index.extend(codes[..7000].to_vec()).unwrap(), 0..7000);
+        for (i, &code) in codes[7000..].iter().enumerate() {
+            assert_eq!(index.insert(code).unwrap(), 7000 + i as u32);
+        }
+        assert_eq!(index.num_codes(), 10000);
+        assert_eq!(index.memtable_len(), 500);
+        for (id, code) in codes.iter().enumerate() {
+            assert_eq!(index.code(id as u32), code);
+        }
+
+        let check = |index: &SegmentedIndex<u64>| {
+            let mut range_searcher = index.range_searcher();
+            let mut topk_searcher = index.topk_searcher();
+            for qi in (0..10000).step_by(100) {
+                let qcode = codes[qi];
+                for rad in 0..6 {
+                    let ans1 = ls::range_search(&codes, qcode, rad);
+                    let ans2 = range_searcher.run(qcode, rad);
+                    assert_eq!(ans1, ans2);
+                }
+
+                let mut dists = ls::exhaustive_search(&codes, qcode);
+                dists.sort_by_key(|x| x.1);
+                for topk in [1, 10, 100] {
+                    let ans = topk_searcher.run(qcode, topk);
+                    assert_eq!(ans.len(), topk);
+                    for (i, &id) in ans.iter().enumerate() {
+                        assert_eq!(
+                            crate::hamdist(qcode, codes[id as usize]),
+                            dists[i].1 as usize
+                        );
+                    }
+                }
+            }
+        };
+
+        check(&index);
+        index.wait_merges().unwrap();
+        check(&index);
+
+        // Segment sizes decrease geometrically
+        for w in index.segments.windows(2) {
+            assert!(MERGE_FACTOR * w[1].index.codes().len() <= w[0].index.codes().len());
+            assert_eq!(
+                w[0].base as usize + w[0].index.codes().len(),
+                w[1].base as usize
+            );
+        }
+        assert!(index.num_segments() <= 4);
+
+        index.flush().unwrap();
+        index.wait_merges().unwrap();
+        assert_eq!(index.memtable_len(), 0);
+        check(&index);
+    }
+
+    #[test]
+    fn segmented_topk_works_with_few_codes() {
+        let mut index = SegmentedIndex::<u8>::with_dimensions(4, 2).unwrap();
+        assert!(index.topk_searcher().run(0, 3).is_empty());
+        index.extend([0b0001, 0b0011, 0b0111]).unwrap();
+        assert_eq!(index.topk_searcher().run(0, 5), &[0, 1, 2]);
+        assert_eq!(index.range_searcher().run(0, 2), &[0, 1]);
+
+        // A query with a bit out of the dimensions, in both the segment and the memtable
+        index.flush().unwrap();
+        index.insert(0b0000).unwrap();
+        assert!(index.topk_searcher().run(0b1_0000, 5).is_empty());
+        assert!(index.range_searcher().run(0b1_0000, 8).is_empty());
+    }
+
+    #[test]
+    fn segmented_insert_fails() {
+        assert!(SegmentedIndex::<u64>::new(0).is_err());
+        assert!(SegmentedIndex::<u64>::with_dimensions(65, 10).is_err());
+
+        let mut index = SegmentedIndex::<u64>::with_dimensions(48, 10).unwrap();
+        assert!(index.insert(1 << 48).is_err());
+        assert_eq!(index.insert(1 << 47).unwrap(), 0);
+    }
+}
@@ -0,0 +1,657 @@
+use anyhow::{anyhow, Result};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::index::{RangeSearcher, TopkSearcher};
+use crate::{CodeInt, Index, IndexBuilder};
+
+/// The maximum number of prefix bits for [`Sharding::Prefix`].
+pub const MAX_PREFIX_BITS: usize = 16;
+
+/// Strategy to split binary codes into shards.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sharding {
+    /// Splits the ids into the given number of contiguous ranges of almost equal sizes.
+    IdRange(usize),
+    /// Splits the codes by the values of their lowest given number of bits,
+    /// i.e., there are `2^num_bits` shards.
+    /// Since the codes in a shard share the prefix, the blocks of each shard consist of only the other bits.
+    /// Searches skip the shards whose prefixes are too far from that of a query code.
+    Prefix(usize),
+}
+
+/// Multi-index hashing split into shards that are built in parallel and searched by scatter-gather.
+///
+/// Each shard is an [`Index`] of a subset of the codes,
+/// so the number of codes is not limited by the `u32` ids of [`Index`].
+/// The codes are identified by global `u64` ids, i.e., the positions in the input codes,
+/// and searches merge the results of the shards exactly.
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::{ShardedIndex, Sharding};
+///
+/// let codes: Vec<u64> = vec![0b1111, 0b0111, 0b0000, 0b0011, 0b1011, 0b0010];
+///
+/// for sharding in [Sharding::IdRange(3), Sharding::Prefix(1)] {
+///     let index = ShardedIndex::new(codes.clone(), sharding).unwrap();
+///
+///     let mut searcher = index.range_searcher();
+///     assert_eq!(searcher.run(0b0011, 1), vec![1, 3, 4, 5]);
+///
+///     let mut searcher = index.topk_searcher();
+///     assert_eq!(searcher.run(0b0011, 1), vec![3]);
+/// }
+/// ```
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ShardedIndex<T: CodeInt> {
+    sharding: Sharding,
+    num_dimensions: usize,
+    num_codes: u64,
+    shards: Vec<Option<Shard<T>>>,
+}
+
+/// Shard of [`ShardedIndex`] that can be serialized and loaded independently.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Shard<T: CodeInt> {
+    index: Index<T>,
+    base: u64,
+    ids: Vec<u64>,
+}
+
+/// Range searcher created by [`ShardedIndex::range_searcher()`].
+pub struct ShardedRangeSearcher<'a, T: CodeInt> {
+    index: &'a ShardedIndex<T>,
+    searchers: Vec<Option<RangeSearcher<'a, T>>>,
+    answers: Vec<u64>,
+}
+
+/// Top-K searcher created by [`ShardedIndex::topk_searcher()`].
+pub struct ShardedTopkSearcher<'a, T: CodeInt> {
+    index: &'a ShardedIndex<T>,
+    searchers: Vec<Option<TopkSearcher<'a, T>>>,
+    candidates: Vec<(usize, u64)>,
+    answers: Vec<u64>,
+}
+
+impl<T> ShardedIndex<T>
+where
+    T: CodeInt + Send,
+{
+    /// Builds a sharded index from binary codes, where each shard is built in parallel with the default parameters.
+    /// The global id of a code is its position in `codes`.
+    ///
+    /// # Arguments
+    ///
+    /// - `codes`: Vector of binary codes of type [`CodeInt`].
+    /// - `sharding`: Strategy to split the codes into shards.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the number of shards of [`Sharding::IdRange`] is zero or more than the number of codes,
+    ///  - the number of bits of [`Sharding::Prefix`] is zero or more than [`MAX_PREFIX_BITS`], or
+    ///  - building a shard fails (e.g., a shard has more than `u32::MAX` codes).
+    pub fn new(codes: Vec<T>, sharding: Sharding) -> Result<Self> {
+        Self::with_builder(codes, sharding, &IndexBuilder::new())
+    }
+
+    /// Builds a sharded index from binary codes, where each shard is built in parallel with `builder`.
+    /// The global id of a code is its position in `codes`.
+    ///
+    /// # Arguments
+    ///
+    /// - `codes`: Vector of binary codes of type [`CodeInt`].
+    /// - `sharding`: Strategy to split the codes into shards.
+    /// - `builder`: Builder of the index of each shard.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned in the same cases as [`ShardedIndex::new()`].
+    pub fn with_builder(codes: Vec<T>, sharding: Sharding, builder: &IndexBuilder) -> Result<Self> {
+        let num_codes = codes.len() as u64;
+        let mut builder = builder.clone();
+        let mut parts: Vec<(u64, Vec<u64>, Vec<T>)> = match sharding {
+            Sharding::IdRange(num_shards) => {
+                if num_shards == 0 || codes.len() < num_shards {
+                    return Err(anyhow!(
+                        "The number of shards {} must be in [1,{}]",
+                        num_shards,
+                        codes.len()
+                    ));
+                }
+                let mut parts = Vec::with_capacity(num_shards);
+                let mut codes = codes;
+                for s in (0..num_shards).rev() {
+                    let beg = codes.len() * s / (s + 1);
+                    parts.push((beg as u64, vec![], codes.split_off(beg)));
+                }
+                parts.reverse();
+                parts
+            }
+            Sharding::Prefix(num_bits) => {
+                if num_bits == 0 || MAX_PREFIX_BITS < num_bits || T::dimensions() < num_bits {
+                    return Err(anyhow!(
+                        "The number of prefix bits {} must be in [1,{}]",
+                        num_bits,
+                        MAX_PREFIX_BITS.min(T::dimensions())
+                    ));
+                }
+                let mut parts = vec![(0, vec![], vec![]); 1 << num_bits];
+                for (id, code) in codes.into_iter().enumerate() {
+                    let part = &mut parts[code.extract(0, num_bits) as usize];
+                    part.1.push(id as u64);
+                    part.2.push(code);
+                }
+                // The prefix bits are the same in a shard, so they are not indexed
+                // but still counted in the verified distances.
+                builder = builder.exclude_low_bits(num_bits);
+                parts
+            }
+        };
+
+        // Each thread builds a contiguous chunk of the shards.
+        let num_shards = parts.len();
+        let num_threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(num_shards);
+        let chunk_size = num_shards.div_ceil(num_threads);
+        let builder = &builder;
+        let shards = std::thread::scope(|scope| {
+            let handles: Vec<_> = parts
+                .chunks_mut(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter_mut()
+                            .map(|(base, ids, codes)| -> Result<Option<Shard<T>>> {
+                                if codes.is_empty() {
+                                    return Ok(None);
+                                }
+                                let index = builder.build(std::mem::take(codes))?;
+                                let ids = std::mem::take(ids);
+                                Ok(Some(Shard {
+                                    index,
+                                    base: *base,
+                                    ids,
+                                }))
+                            })
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+            let mut shards = Vec::with_capacity(num_shards);
+            for handle in handles {
+                let chunk = handle
+                    .join()
+                    .map_err(|_| anyhow!("The construction of a shard panicked"))??;
+                shards.extend(chunk);
+            }
+            Ok::<_, anyhow::Error>(shards)
+        })?;
+
+        let num_dimensions = shards
+            .iter()
+            .flatten()
+            .map(|shard| shard.index.num_dimensions())
+            .next()
+            .ok_or_else(|| anyhow!("The input codes must not be empty"))?;
+
+        Ok(Self {
+            sharding,
+            num_dimensions,
+            num_codes,
+            shards,
+        })
+    }
+}
+
+impl<T: CodeInt> ShardedIndex<T> {
+    /// Assembles a sharded index from shards, e.g., loaded with [`Shard::deserialize_from()`].
+    ///
+    /// # Arguments
+    ///
+    /// - `sharding`: Strategy with which the shards were built.
+    /// - `shards`: The shards in the original order, where `None` represents an empty shard.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the number of shards is inconsistent with `sharding`,
+    ///  - all the shards are empty, or
+    ///  - the shards have different numbers of dimensions.
+    pub fn from_shards(sharding: Sharding, shards: Vec<Option<Shard<T>>>) -> Result<Self> {
+        let num_shards = match sharding {
+            Sharding::IdRange(num_shards) => num_shards,
+            Sharding::Prefix(num_bits) if num_bits <= MAX_PREFIX_BITS => 1 << num_bits,
+            Sharding::Prefix(num_bits) => {
+                return Err(anyhow!(
+                    "The number of prefix bits {} must be in [1,{}]",
+                    num_bits,
+                    MAX_PREFIX_BITS
+                ))
+            }
+        };
+        if shards.len() != num_shards {
+            return Err(anyhow!(
+                "The number of shards {} must be {}",
+                shards.len(),
+                num_shards
+            ));
+        }
+
+        let num_dimensions = shards
+            .iter()
+            .flatten()
+            .map(|shard| shard.index.num_dimensions())
+            .next()
+            .ok_or_else(|| anyhow!("The shards must not be all empty"))?;
+        if shards
+            .iter()
+            .flatten()
+            .any(|shard| shard.index.num_dimensions() != num_dimensions)
+        {
+            return Err(anyhow!(
+                "The numbers of dimensions of the shards must be the same"
+            ));
+        }
+
+        let num_codes = shards
+            .iter()
+            .flatten()
+            .map(|shard| shard.index.codes().len() as u64)
+            .sum();
+
+        Ok(Self {
+            sharding,
+            num_dimensions,
+            num_codes,
+            shards,
+        })
+    }
+
+    /// Returns a searcher [`ShardedRangeSearcher`] to find neighbor codes
+    /// whose Hamming distances to a query code are within a query radius.
+    pub fn range_searcher(&self) -> ShardedRangeSearcher<'_, T> {
+        ShardedRangeSearcher {
+            index: self,
+            searchers: self
+                .shards
+                .iter()
+                .map(|shard| shard.as_ref().map(|s| s.index.range_searcher()))
+                .collect(),
+            answers: Vec::with_capacity(1 << 10),
+        }
+    }
+
+    /// Returns a searcher [`ShardedTopkSearcher`] to find top-K codes that are closest to a query code.
+    pub fn topk_searcher(&self) -> ShardedTopkSearcher<'_, T> {
+        ShardedTopkSearcher {
+            index: self,
+            searchers: self
+                .shards
+                .iter()
+                .map(|shard| shard.as_ref().map(|s| s.index.topk_searcher()))
+                .collect(),
+            candidates: Vec::with_capacity(1 << 10),
+            answers: Vec::with_capacity(1 << 10),
+        }
+    }
+
+    /// Gets the strategy with which the codes were split.
+    pub const fn sharding(&self) -> Sharding {
+        self.sharding
+    }
+
+    /// Gets the shards, where `None` represents an empty shard.
+    pub fn shards(&self) -> &[Option<Shard<T>>] {
+        &self.shards
+    }
+
+    /// Gets the total number of codes.
+    pub const fn num_codes(&self) -> u64 {
+        self.num_codes
+    }
+
+    /// Gets the number of meaningful bits in a binary code.
+    pub const fn num_dimensions(&self) -> usize {
+        self.num_dimensions
+    }
+
+    /// Serializes the index into the file.
+    pub fn serialize_into<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
+        let (tag, param) = match self.sharding {
+            Sharding::IdRange(num_shards) => (0, num_shards),
+            Sharding::Prefix(num_bits) => (1, num_bits),
+        };
+        writer.write_u64::<LittleEndian>(tag)?;
+        writer.write_u64::<LittleEndian>(param as u64)?;
+        for shard in &self.shards {
+            match shard {
+                None => writer.write_u8(0)?,
+                Some(shard) => {
+                    writer.write_u8(1)?;
+                    shard.serialize_into(&mut writer)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Deserializes the index from the file.
+    pub fn deserialize_from<R: std::io::Read>(mut reader: R) -> Result<Self> {
+        let tag = reader.read_u64::<LittleEndian>()?;
+        let param = reader.read_u64::<LittleEndian>()? as usize;
+        let (sharding, num_shards) = match tag {
+            0 => (Sharding::IdRange(param), param),
+            1 if param <= MAX_PREFIX_BITS => (Sharding::Prefix(param), 1 << param),
+            _ => return Err(anyhow!("The sharding {} is invalid", tag)),
+        };
+        let mut shards = Vec::with_capacity(num_shards);
+        for _ in 0..num_shards {
+            shards.push(match reader.read_u8()? {
+                0 => None,
+                _ => Some(Shard::deserialize_from(&mut reader)?),
+            });
+        }
+        Self::from_shards(sharding, shards)
+    }
+
+    /// Gets the prefix of a code used by [`Sharding::Prefix`].
+    fn prefix(&self, code: &T) -> Option<u64> {
+        match self.sharding {
+            Sharding::IdRange(_) => None,
+            Sharding::Prefix(num_bits) => Some(code.extract(0, num_bits)),
+        }
+    }
+}
+
+impl<T: CodeInt> Shard<T> {
+    /// Gets the index of the codes in the shard.
+    pub const fn index(&self) -> &Index<T> {
+        &self.index
+    }
+
+    /// Converts an id in [`Shard::index()`] into the global id.
+    pub fn global_id(&self, id: u32) -> u64 {
+        if self.ids.is_empty() {
+            self.base + id as u64
+        } else {
+            self.ids[id as usize]
+        }
+    }
+
+    /// Serializes the shard into the file.
+    pub fn serialize_into<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
+        self.index.serialize_into(&mut writer)?;
+        writer.write_u64::<LittleEndian>(self.base)?;
+        writer.write_u64::<LittleEndian>(self.ids.len() as u64)?;
+        for &x in &self.ids {
+            writer.write_u64::<LittleEndian>(x)?;
+        }
+        Ok(())
+    }
+
+    /// Deserializes the shard from the file.
+    pub fn deserialize_from<R: std::io::Read>(mut reader: R) -> Result<Self> {
+        let index = Index::deserialize_from(&mut reader)?;
+        let base = reader.read_u64::<LittleEndian>()?;
+        let ids = {
+            let len = reader.read_u64::<LittleEndian>()? as usize;
+            let mut ids = Vec::with_capacity(len);
+            for _ in 0..len {
+                ids.push(reader.read_u64::<LittleEndian>()?);
+            }
+            ids
+        };
+        Ok(Self { index, base, ids })
+    }
+}
+
+impl<'a, T> ShardedRangeSearcher<'a, T>
+where
+    T: CodeInt,
+{
+    /// Searches neighbor codes whose Hamming distances to a query code are within a query radius.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `radius`: Threshold to be searched.
+    ///
+    /// # Returns
+    ///
+    /// A slice of global ids of codes whose Hamming distances to `qcode` are within `radius`.
+    /// The ids are sorted.
+    /// Note that the values of the slice will be updated in the next [`ShardedRangeSearcher::run()`].
+    pub fn run(&mut self, qcode: T, radius: usize) -> &[u64] {
+        self.answers.clear();
+        let qprefix = self.index.prefix(&qcode);
+
+        for (s, (shard, searcher)) in self
+            .index
+            .shards
+            .iter()
+            .zip(self.searchers.iter_mut())
+            .enumerate()
+        {
+            let (Some(shard), Some(searcher)) = (shard, searcher) else {
+                continue;
+            };
+            // The prefix distance is a lower bound of the distances in the shard.
+            if qprefix.is_some_and(|q| radius < (q ^ s as u64).count_ones() as usize) {
+                continue;
+            }
+            for &id in searcher.run(qcode.clone(), radius) {
+                self.answers.push(shard.global_id(id));
+            }
+        }
+
+        if qprefix.is_some() {
+            self.answers.sort_unstable();
+        }
+        &self.answers
+    }
+}
+
+impl<'a, T> ShardedTopkSearcher<'a, T>
+where
+    T: CodeInt,
+{
+    /// Searches top-K codes that are closest to a query code.
+    ///
+    /// # Arguments
+    ///
+    /// - `qcode`: Binary code of the query.
+    /// - `topk`: Threshold to be searched.
+    ///
+    /// # Returns
+    ///
+    /// A slice of global ids of the `topk` nearest neighbor codes to `qcode`.
+    /// The ids are sorted in the Hamming distances to `qcode`.
+    /// If `topk` is more than the number of codes, all of them are returned.
+    /// Note that the values of the slice will be updated in the next [`ShardedTopkSearcher::run()`].
+    pub fn run(&mut self, qcode: T, topk: usize) -> &[u64] {
+        self.candidates.clear();
+        self.answers.clear();
+        if topk == 0 {
+            return &self.answers;
+        }
+
+        // Visits the shards in the order of the prefix distances to stop early.
+        let qprefix = self.index.prefix(&qcode);
+        let lower_bound = |s: usize| qprefix.map_or(0, |q| (q ^ s as u64).count_ones() as usize);
+        let mut order: Vec<usize> = (0..self.index.shards.len()).collect();
+        order.sort_by_key(|&s| lower_bound(s));
+
+        for s in order {
+            let (Some(shard), Some(searcher)) = (&self.index.shards[s], &mut self.searchers[s])
+            else {
+                continue;
+            };
+            if topk <= self.candidates.len() {
+                self.candidates.select_nth_unstable(topk - 1);
+                self.candidates.truncate(topk);
+                if self.candidates[topk - 1].0 < lower_bound(s) {
+                    break;
+                }
+            }
+            let codes = shard.index.codes();
+            for &id in searcher.run(qcode.clone(), topk) {
+                let dist = qcode.distance(&codes[id as usize]);
+                self.candidates.push((dist, shard.global_id(id)));
+            }
+        }
+
+        self.candidates.sort_unstable();
+        self.answers
+            .extend(self.candidates.iter().take(topk).map(|&(_, id)| id));
+        &self.answers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ls, BitPermutation, BlockWidths};
+
+    use rand::{thread_rng, Rng};
+
+    fn check_search(index: &ShardedIndex<u64>, codes: &[u64]) {
+        let mut range_searcher = index.range_searcher();
+        let mut topk_searcher = index.topk_searcher();
+        for qi in (0..codes.len()).step_by(100) {
+            let qcode = codes[qi];
+            for rad in 0..6 {
+                let ans1: Vec<u64> = ls::range_search(codes, qcode, rad)
+                    .into_iter()
+                    .map(|id| id as u64)
+                    .collect();
+                let ans2 = range_searcher.run(qcode, rad);
+                assert_eq!(ans1, ans2);
+            }
+
+            let mut dists = ls::exhaustive_search(codes, qcode);
+            dists.sort_by_key(|x| x.1);
+            for topk in [1, 10, 100] {
+                let ans = topk_searcher.run(qcode, topk);
+                assert_eq!(ans.len(), topk);
+                for (i, &id) in ans.iter().enumerate() {
+                    assert_eq!(
+                        crate::hamdist(qcode, codes[id as usize]),
+                        dists[i].1 as usize
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sharded_search_works() {
+        let mut rng = thread_rng();
+        let codes: Vec<u64> = (0..10000).map(|_| rng.gen()).collect();
+
+        for sharding in [
+            Sharding::IdRange(1),
+            Sharding::IdRange(7),
+            Sharding::Prefix(3),
+        ] {
+            let index = ShardedIndex::new(codes.clone(), sharding).unwrap();
+            assert_eq!(index.num_codes(), 10000);
+            assert_eq!(index.num_dimensions(), 64);
+            check_search(&index, &codes);
+
+            for shard in index.shards().iter().flatten() {
+                for (id, code) in shard.index().codes().iter().enumerate() {
+                    assert_eq!(codes[shard.global_id(id as u32) as usize], *code);
+                }
+            }
+
+            let mut data = vec![];
+            index.serialize_into(&mut data).unwrap();
+            let other = ShardedIndex::<u64>::deserialize_from(&data[..]).unwrap();
+            assert_eq!(index, other);
+        }
+    }
+
+    #[test]
+    fn shard_io_works() {
+        let mut rng = thread_rng();
+        let codes: Vec<u64> = (0..10000).map(|_| rng.gen()).collect();
+        let builder = IndexBuilder::new().num_blocks(4);
+        let index =
+            ShardedIndex::with_builder(codes.clone(), Sharding::Prefix(2), &builder).unwrap();
+
+        // Loads each shard independently
+        let mut shards = vec![];
+        for shard in index.shards() {
+            let shard = shard.as_ref().unwrap();
+            assert_eq!(shard.index().num_blocks(), 4);
+            let mut data = vec![];
+            shard.serialize_into(&mut data).unwrap();
+            shards.push(Some(Shard::<u64>::deserialize_from(&data[..]).unwrap()));
+        }
+        let other = ShardedIndex::from_shards(Sharding::Prefix(2), shards.clone()).unwrap();
+        assert_eq!(index, other);
+        check_search(&other, &codes);
+
+        assert!(ShardedIndex::from_shards(Sharding::Prefix(3), shards.clone()).is_err());
+        assert!(ShardedIndex::<u64>::from_shards(Sharding::IdRange(4), vec![None; 4]).is_err());
+    }
+
+    #[test]
+    fn prefix_shards_exclude_prefix_bits() {
+        let mut rng = thread_rng();
+        let codes: Vec<u64> = (0..10000).map(|_| rng.gen()).collect();
+        let partition: Vec<Vec<usize>> = (0..4).map(|b| (b..64).step_by(4).collect()).collect();
+
+        for builder in [
+            IndexBuilder::new(),
+            IndexBuilder::new().num_blocks(4),
+            IndexBuilder::new().permutation(BitPermutation::Random(7)),
+            IndexBuilder::new().permutation(BitPermutation::Correlation),
+            IndexBuilder::new().block_widths(BlockWidths::CostModel { radius: 4 }),
+            IndexBuilder::new().partition(partition.clone()),
+        ] {
+            let index =
+                ShardedIndex::with_builder(codes.clone(), Sharding::Prefix(3), &builder).unwrap();
+            for shard in index.shards().iter().flatten() {
+                let shard_index = shard.index();
+                assert_eq!(shard_index.num_dimensions(), 64);
+                let mut bits: Vec<usize> = (0..shard_index.num_blocks())
+                    .flat_map(|b| shard_index.block(b).iter().copied())
+                    .collect();
+                bits.sort_unstable();
+                assert_eq!(bits, (3..64).collect::<Vec<_>>());
+            }
+            check_search(&index, &codes);
+
+            let mut data = vec![];
+            index.serialize_into(&mut data).unwrap();
+            let other = ShardedIndex::<u64>::deserialize_from(&data[..]).unwrap();
+            assert_eq!(index, other);
+        }
+
+        // Blocks of only prefix bits become empty.
+        let builder = IndexBuilder::new().partition(vec![vec![0, 1], (2..64).collect()]);
+        assert!(ShardedIndex::with_builder(codes, Sharding::Prefix(2), &builder).is_err());
+    }
+
+    #[test]
+    fn sharded_with_empty_shards_works() {
+        let codes: Vec<u64> = vec![0b0100, 0b0110, 0b1100, 0b1000];
+        let index = ShardedIndex::new(codes.clone(), Sharding::Prefix(2)).unwrap();
+        assert_eq!(index.shards().iter().flatten().count(), 2);
+        assert_eq!(index.range_searcher().run(0b0111, 2), &[0, 1]);
+        assert_eq!(index.topk_searcher().run(0b0111, 10), &[1, 0, 2, 3]);
+
+        assert!(ShardedIndex::<u64>::new(vec![], Sharding::Prefix(2)).is_err());
+        assert!(ShardedIndex::new(codes.clone(), Sharding::IdRange(0)).is_err());
+        assert!(ShardedIndex::new(codes.clone(), Sharding::IdRange(5)).is_err());
+        assert!(ShardedIndex::new(codes.clone(), Sharding::Prefix(0)).is_err());
+        assert!(ShardedIndex::new(codes, Sharding::Prefix(MAX_PREFIX_BITS + 1)).is_err());
+    }
+}
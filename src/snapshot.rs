@@ -0,0 +1,265 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use arc_swap::ArcSwap;
+
+use crate::{CodeInt, Index};
+
+/// [`Index`] shared by concurrent readers and writers through immutable versions.
+///
+/// Readers take a *snapshot*, i.e., an `Arc` of the latest published version,
+/// and search it without any lock while writers mutate the index.
+/// Each mutation is applied to a private version and published by an atomic pointer swap,
+/// so a snapshot always shows a consistent state.
+/// Taking a snapshot is lock-free and never waits for writers, even during the swap;
+/// only writers are serialized with each other.
+/// A version is reclaimed when the last snapshot holding it is dropped.
+///
+/// Internally, the writer keeps the previously published version as a spare and,
+/// if no reader holds it anymore, brings it up to date by replaying the last mutation
+/// instead of cloning the whole index.
+/// Hence, two copies of the index are kept in the steady state.
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::{Index, SnapshotIndex};
+///
+/// let index = Index::with_blocks(vec![0b0000u8, 0b1111], 2).unwrap();
+/// let shared = SnapshotIndex::new(index);
+///
+/// let snapshot = shared.snapshot();
+/// shared.insert(0b0111).unwrap();
+///
+/// // The snapshot is not affected by the insertion.
+/// let mut searcher = snapshot.range_searcher();
+/// assert_eq!(searcher.run(0b0011, 2), vec![0, 1]);
+///
+/// let snapshot = shared.snapshot();
+/// let mut searcher = snapshot.range_searcher();
+/// assert_eq!(searcher.run(0b0011, 2), vec![0, 1, 2]);
+/// ```
+pub struct SnapshotIndex<T: CodeInt> {
+    current: ArcSwap<Index<T>>,
+    writer: Mutex<Writer<T>>,
+}
+
+/// State of the writer.
+struct Writer<T: CodeInt> {
+    /// Previously published version.
+    spare: Option<Arc<Index<T>>>,
+    /// Mutations applied to the current version but not to the spare.
+    lag: Vec<Mutation<T>>,
+}
+
+enum Mutation<T> {
+    Extend(Vec<T>),
+    Remove(u32),
+    Update(u32, T),
+}
+
+impl<T: CodeInt> Mutation<T> {
+    fn apply(&self, index: &mut Index<T>) -> Result<()> {
+        match self {
+            Self::Extend(codes) => index.extend(codes.iter().cloned()).map(|_| ()),
+            Self::Remove(id) => index.remove(*id),
+            Self::Update(id, code) => index.update(*id, code.clone()),
+        }
+    }
+}
+
+impl<T: CodeInt> SnapshotIndex<T> {
+    /// Creates a shared index whose first version is `index`.
+    pub fn new(index: Index<T>) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(index),
+            writer: Mutex::new(Writer {
+                spare: None,
+                lag: vec![],
+            }),
+        }
+    }
+
+    /// Gets the latest published version.
+    ///
+    /// The snapshot is immutable and unaffected by later mutations.
+    /// This method is lock-free.
+    pub fn snapshot(&self) -> Arc<Index<T>> {
+        self.current.load_full()
+    }
+
+    /// Inserts a binary code and publishes a new version.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when [`Index::insert()`] fails.
+    pub fn insert(&self, code: T) -> Result<u32> {
+        self.extend(std::iter::once(code)).map(|ids| ids.start)
+    }
+
+    /// Inserts binary codes and publishes a new version containing all of them.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when [`Index::extend()`] fails.
+    pub fn extend<I>(&self, codes: I) -> Result<std::ops::Range<u32>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let codes: Vec<T> = codes.into_iter().collect();
+        let len = codes.len() as u32;
+        let start = self.publish(Mutation::Extend(codes))? as u32;
+        Ok(start..start + len)
+    }
+
+    /// Removes the code of an id and publishes a new version.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when [`Index::remove()`] fails.
+    pub fn remove(&self, id: u32) -> Result<()> {
+        self.publish(Mutation::Remove(id)).map(|_| ())
+    }
+
+    /// Replaces the code of an id and publishes a new version.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when [`Index::update()`] fails.
+    pub fn update(&self, id: u32, code: T) -> Result<()> {
+        self.publish(Mutation::Update(id, code)).map(|_| ())
+    }
+
+    /// Applies a mutation to a new version and publishes it,
+    /// returning the number of codes before the mutation.
+    fn publish(&self, mutation: Mutation<T>) -> Result<usize> {
+        let mut writer = self.writer.lock().unwrap();
+        let current = self.snapshot();
+        let num_codes = current.codes().len();
+
+        // Reuses the spare if no reader holds it, or clones the current version otherwise.
+        let lag = std::mem::take(&mut writer.lag);
+        let mut next = writer
+            .spare
+            .take()
+            .and_then(|mut spare| {
+                let index = Arc::get_mut(&mut spare)?;
+                lag.iter().try_for_each(|m| m.apply(index)).ok()?;
+                Some(spare)
+            })
+            .unwrap_or_else(|| Arc::new(Index::clone(&current)));
+        drop(current);
+
+        if let Err(e) = mutation.apply(Arc::get_mut(&mut next).unwrap()) {
+            // The failed mutation leaves the index unchanged, so it is up to date.
+            writer.spare = Some(next);
+            return Err(e);
+        }
+
+        let prev = self.current.swap(next);
+        writer.spare = Some(prev);
+        writer.lag.push(mutation);
+        Ok(num_codes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ls;
+    use rand::{thread_rng, Rng};
+
+    #[test]
+    fn snapshot_works() {
+        let mut rng = thread_rng();
+        let codes: Vec<u64> = (0..1000).map(|_| rng.gen()).collect();
+        let shared = SnapshotIndex::new(Index::new(codes.clone()).unwrap());
+        let mut expected = Index::new(codes).unwrap();
+
+        for i in 0..100 {
+            let old = shared.snapshot();
+            let old_expected = expected.clone();
+            match i % 4 {
+                0 => {
+                    let code = rng.gen();
+                    assert_eq!(shared.insert(code).unwrap(), expected.insert(code).unwrap());
+                }
+                1 => {
+                    let codes: Vec<u64> = (0..10).map(|_| rng.gen()).collect();
+                    assert_eq!(
+                        shared.extend(codes.clone()).unwrap(),
+                        expected.extend(codes).unwrap()
+                    );
+                }
+                2 => {
+                    shared.remove(i).unwrap();
+                    expected.remove(i).unwrap();
+                    assert!(shared.remove(i).is_err());
+                }
+                _ => {
+                    let code = rng.gen();
+                    shared.update(i, code).unwrap();
+                    expected.update(i, code).unwrap();
+                }
+            }
+            assert_eq!(*old, old_expected);
+            assert_eq!(*shared.snapshot(), expected);
+        }
+    }
+
+    #[test]
+    fn old_version_is_reclaimed() {
+        let shared = SnapshotIndex::new(Index::new(vec![0u64, 1, 2, 3]).unwrap());
+
+        let snapshot = shared.snapshot();
+        let weak = Arc::downgrade(&snapshot);
+        shared.insert(4).unwrap();
+        shared.insert(5).unwrap();
+        assert!(weak.upgrade().is_some());
+        drop(snapshot);
+        assert!(weak.upgrade().is_none());
+
+        // Without readers, the spare is reused.
+        let ptr = Arc::as_ptr(&shared.snapshot());
+        shared.insert(6).unwrap();
+        shared.insert(7).unwrap();
+        assert_eq!(Arc::as_ptr(&shared.snapshot()), ptr);
+        assert_eq!(shared.snapshot().codes(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn concurrent_reads_work() {
+        let mut rng = thread_rng();
+        let codes: Vec<u64> = (0..1000).map(|_| rng.gen()).collect();
+        let shared = Arc::new(SnapshotIndex::new(Index::new(codes).unwrap()));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let shared = Arc::clone(&shared);
+                std::thread::spawn(move || {
+                    let mut rng = thread_rng();
+                    let mut prev_len = 0;
+                    while prev_len < 1500 {
+                        let snapshot = shared.snapshot();
+                        assert!(prev_len <= snapshot.codes().len());
+                        prev_len = snapshot.codes().len();
+
+                        let qcode = snapshot.codes()[rng.gen_range(0..prev_len)];
+                        let mut searcher = snapshot.range_searcher();
+                        let answers = searcher.run(qcode, 20);
+                        let expected = ls::range_search(snapshot.codes(), qcode, 20);
+                        assert_eq!(answers, &expected[..]);
+                    }
+                })
+            })
+            .collect();
+
+        for _ in 0..500 {
+            shared.insert(rng.gen()).unwrap();
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,639 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{CodeInt, Index};
+
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+const LOG_FILE: &str = "wal";
+
+const OP_INSERT: u8 = 0;
+const OP_REMOVE: u8 = 1;
+const OP_UPDATE: u8 = 2;
+
+/// [`Index`] made crash-safe by a write-ahead log.
+///
+/// The state is stored in a directory as a *snapshot* of the index and
+/// an append-only *log* of the mutations applied after the snapshot.
+/// Each record of the log has a sequence number and a CRC-32 checksum.
+/// [`DurableIndex::open()`] loads the snapshot and replays the log,
+/// and [`DurableIndex::checkpoint()`] writes a fresh snapshot and truncates the log.
+///
+/// Each mutation is validated and its record is written to the log before the mutation is applied
+/// to the in-memory index. If the process crashes while appending a record, the torn record is detected
+/// by its length or checksum and dropped at the next recovery.
+///
+/// If an I/O error occurs while writing the log, the mutation is not applied,
+/// and the log is truncated to the last complete record as far as possible.
+/// The durable index is then *poisoned*, i.e., all the following mutations fail,
+/// and the state should be recovered with [`DurableIndex::open()`].
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::{DurableIndex, Index};
+///
+/// let dir = std::env::temp_dir().join("mih-rs-doctest-durable");
+/// let index = Index::with_blocks(vec![0b0000u8, 0b1111], 2).unwrap();
+///
+/// let mut durable = DurableIndex::create(&dir, index).unwrap();
+/// durable.insert(0b0111).unwrap();
+/// durable.update(0, 0b0001).unwrap();
+/// durable.remove(1).unwrap();
+/// drop(durable);
+///
+/// // Recovers the snapshot and the logged mutations
+/// let durable = DurableIndex::<u8>::open(&dir).unwrap();
+/// assert_eq!(durable.index().codes(), &[0b0001, 0b1111, 0b0111]);
+///
+/// let mut searcher = durable.index().range_searcher();
+/// assert_eq!(searcher.run(0b0011, 1), vec![0, 2]);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct DurableIndex<T: CodeInt> {
+    dir: PathBuf,
+    index: Index<T>,
+    log: File,
+    // Length of the complete records in the log
+    log_len: u64,
+    seq: u64,
+    sync: bool,
+    poisoned: bool,
+}
+
+impl<T: CodeInt> DurableIndex<T> {
+    /// Creates a durable index in a directory, starting from the snapshot of `index`.
+    /// The directory is created if it does not exist, and its previous state is overwritten.
+    ///
+    /// The previous log is truncated before the new snapshot is written,
+    /// so its records are never replayed onto the new index even if a crash occurs in between.
+    ///
+    /// # Arguments
+    ///
+    /// - `dir`: Directory to store the snapshot and the log.
+    /// - `index`: Initial index.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when an I/O error occurs.
+    pub fn create<P: AsRef<Path>>(dir: P, index: Index<T>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let log = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(dir.join(LOG_FILE))?;
+        log.sync_all()?;
+        write_snapshot(&dir, &index, 0)?;
+        Ok(Self {
+            dir,
+            index,
+            log,
+            log_len: 0,
+            seq: 0,
+            sync: true,
+            poisoned: false,
+        })
+    }
+
+    /// Opens a durable index in a directory created by [`DurableIndex::create()`],
+    /// recovering the state from the snapshot and the log.
+    ///
+    /// The records of the log are replayed in order until the end or the first invalid record,
+    /// i.e., one that is incomplete or has a wrong checksum.
+    /// The invalid record is regarded as torn by a crash and truncated together with the following bytes.
+    ///
+    /// # Arguments
+    ///
+    /// - `dir`: Directory storing the snapshot and the log.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - an I/O error occurs,
+    ///  - the snapshot is broken, or
+    ///  - a valid record of the log cannot be applied to the index.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let (mut index, snapshot_seq) = {
+            let mut reader = BufReader::new(File::open(dir.join(SNAPSHOT_FILE))?);
+            let seq = reader.read_u64::<LittleEndian>()?;
+            (Index::deserialize_from(&mut reader)?, seq)
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(dir.join(LOG_FILE))?;
+        let file_len = file.metadata()?.len();
+        let mut seq = snapshot_seq;
+        let mut valid_len = 0;
+        {
+            let mut reader = BufReader::new(&mut file);
+            while let Some((record_seq, payload, len)) =
+                read_record(&mut reader, file_len - valid_len)?
+            {
+                // Records before the snapshot remain if a crash occurs during a checkpoint.
+                if snapshot_seq < record_seq {
+                    apply(&mut index, &payload)?;
+                    seq = record_seq;
+                }
+                valid_len += len;
+            }
+        }
+        file.set_len(valid_len)?;
+        file.seek(SeekFrom::End(0))?;
+        file.sync_all()?;
+
+        Ok(Self {
+            dir,
+            index,
+            log: file,
+            log_len: valid_len,
+            seq,
+            sync: true,
+            poisoned: false,
+        })
+    }
+
+    /// Sets whether each mutation waits for the log to be synchronized to the disk (`true` by default).
+    /// If `false`, the mutations are durable against process crashes but may be lost by power failures
+    /// until [`DurableIndex::sync()`] or [`DurableIndex::checkpoint()`] is called.
+    pub fn set_sync(&mut self, sync: bool) {
+        self.sync = sync;
+    }
+
+    /// Inserts a binary code in the same manner as [`Index::insert()`] and logs it.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - [`Index::insert()`] would fail,
+    ///  - the durable index has been poisoned, or
+    ///  - an I/O error occurs, in which case the code is not inserted.
+    pub fn insert(&mut self, code: T) -> Result<u32> {
+        self.index
+            .check_insertable(&code, self.index.codes().len())?;
+        let mut payload = vec![OP_INSERT];
+        code.serialize_into(&mut payload)?;
+        self.append(&payload)?;
+        self.index.insert(code)
+    }
+
+    /// Removes the code of an id in the same manner as [`Index::remove()`] and logs it.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - [`Index::remove()`] would fail,
+    ///  - the durable index has been poisoned, or
+    ///  - an I/O error occurs, in which case the code is not removed.
+    pub fn remove(&mut self, id: u32) -> Result<()> {
+        self.index.check_live(id)?;
+        let mut payload = vec![OP_REMOVE];
+        payload.write_u32::<LittleEndian>(id)?;
+        self.append(&payload)?;
+        self.index.remove(id)
+    }
+
+    /// Replaces the code of an id in the same manner as [`Index::update()`] and logs it.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - [`Index::update()`] would fail,
+    ///  - the durable index has been poisoned, or
+    ///  - an I/O error occurs, in which case the code is not updated.
+    pub fn update(&mut self, id: u32, code: T) -> Result<()> {
+        self.index.check_live(id)?;
+        self.index.check_insertable(&code, id as usize)?;
+        let mut payload = vec![OP_UPDATE];
+        payload.write_u32::<LittleEndian>(id)?;
+        code.serialize_into(&mut payload)?;
+        self.append(&payload)?;
+        self.index.update(id, code)
+    }
+
+    /// Synchronizes the log to the disk.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the durable index has been poisoned, or
+    ///  - an I/O error occurs, in which case the durable index is poisoned.
+    pub fn sync(&mut self) -> Result<()> {
+        self.check_poisoned()?;
+        if let Err(e) = self.log.sync_data() {
+            self.poisoned = true;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    /// Writes a fresh snapshot of the current index and truncates the log.
+    ///
+    /// The snapshot is written to a temporary file and renamed atomically,
+    /// so the previous state can be recovered if a crash occurs during the checkpoint.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - the durable index has been poisoned, or
+    ///  - an I/O error occurs, in which case the durable index is poisoned
+    ///    if the log may have been modified.
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.sync()?;
+        write_snapshot(&self.dir, &self.index, self.seq)?;
+        let truncated = (|| -> std::io::Result<()> {
+            self.log.set_len(0)?;
+            self.log.seek(SeekFrom::Start(0))?;
+            self.log.sync_all()
+        })();
+        if let Err(e) = truncated {
+            self.poisoned = true;
+            return Err(e.into());
+        }
+        self.log_len = 0;
+        Ok(())
+    }
+
+    /// Checks if the durable index has been poisoned by an I/O error.
+    pub const fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
+    /// Gets the reference of the index.
+    pub const fn index(&self) -> &Index<T> {
+        &self.index
+    }
+
+    fn check_poisoned(&self) -> Result<()> {
+        if self.poisoned {
+            return Err(anyhow!(
+                "The durable index must not have been poisoned by an I/O error"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Appends a record with the next sequence number to the log.
+    /// If an I/O error occurs, the torn record is truncated as far as possible
+    /// and the durable index is poisoned.
+    fn append(&mut self, payload: &[u8]) -> Result<()> {
+        self.check_poisoned()?;
+
+        let seq = self.seq + 1;
+        let mut body = Vec::with_capacity(8 + payload.len());
+        body.write_u64::<LittleEndian>(seq)?;
+        body.extend_from_slice(payload);
+
+        let mut record = Vec::with_capacity(8 + body.len());
+        record.write_u32::<LittleEndian>(body.len() as u32)?;
+        record.write_u32::<LittleEndian>(crc32(&body))?;
+        record.extend_from_slice(&body);
+
+        let mut written = self.log.write_all(&record);
+        if written.is_ok() && self.sync {
+            written = self.log.sync_data();
+        }
+        if let Err(e) = written {
+            self.poisoned = true;
+            if self.log.set_len(self.log_len).is_ok() {
+                self.log.seek(SeekFrom::Start(self.log_len)).ok();
+                self.log.sync_data().ok();
+            }
+            return Err(e.into());
+        }
+
+        self.log_len += record.len() as u64;
+        self.seq = seq;
+        Ok(())
+    }
+}
+
+/// Writes the snapshot of `index` after the record `seq` atomically.
+fn write_snapshot<T: CodeInt>(dir: &Path, index: &Index<T>, seq: u64) -> Result<()> {
+    let tmp_path = dir.join(SNAPSHOT_TMP_FILE);
+    {
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        writer.write_u64::<LittleEndian>(seq)?;
+        index.serialize_into(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+    }
+    std::fs::rename(&tmp_path, dir.join(SNAPSHOT_FILE))?;
+    // Makes the rename durable.
+    if let Ok(dir) = File::open(dir) {
+        dir.sync_all().ok();
+    }
+    Ok(())
+}
+
+/// Reads the next record within the `remaining` bytes of the log,
+/// returning its sequence number, payload, and length in bytes.
+/// Returns `None` at the end of the log or at an invalid record.
+fn read_record<R: Read>(mut reader: R, remaining: u64) -> Result<Option<(u64, Vec<u8>, u64)>> {
+    let mut header = [0; 8];
+    if !read_full(&mut reader, &mut header)? {
+        return Ok(None);
+    }
+    let len = LittleEndian::read_u32(&header[..4]) as usize;
+    let checksum = LittleEndian::read_u32(&header[4..]);
+    // A torn header may have a length over the log, which must be rejected before allocating.
+    if len < 8 || remaining < (header.len() + len) as u64 {
+        return Ok(None);
+    }
+
+    let mut body = vec![0; len];
+    if !read_full(&mut reader, &mut body)? || crc32(&body) != checksum {
+        return Ok(None);
+    }
+    let seq = LittleEndian::read_u64(&body[..8]);
+    Ok(Some((seq, body.split_off(8), (header.len() + len) as u64)))
+}
+
+/// Fills `buf`, returning `false` if the reader reaches the end before.
+fn read_full<R: Read>(mut reader: R, buf: &mut [u8]) -> Result<bool> {
+    let mut pos = 0;
+    while pos < buf.len() {
+        match reader.read(&mut buf[pos..]) {
+            Ok(0) => return Ok(false),
+            Ok(n) => pos += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+/// Applies the mutation of a record to the index.
+fn apply<T: CodeInt>(index: &mut Index<T>, payload: &[u8]) -> Result<()> {
+    let (&op, mut reader) = payload
+        .split_first()
+        .ok_or_else(|| anyhow!("The log record must not be empty"))?;
+    match op {
+        OP_INSERT => {
+            index.insert(T::deserialize_from(&mut reader)?)?;
+        }
+        OP_REMOVE => {
+            index.remove(reader.read_u32::<LittleEndian>()?)?;
+        }
+        OP_UPDATE => {
+            let id = reader.read_u32::<LittleEndian>()?;
+            index.update(id, T::deserialize_from(&mut reader)?)?;
+        }
+        _ => return Err(anyhow!("The log record has an unknown operation {}", op)),
+    }
+    Ok(())
+}
+
+/// Computes the CRC-32 (IEEE) checksum.
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    0xEDB88320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+
+    let mut crc = !0u32;
+    for &b in data {
+        crc = TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{thread_rng, Rng};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mih-rs-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        dir
+    }
+
+    /// Applies random mutations to both the durable index and the expected codes.
+    fn mutate(durable: &mut DurableIndex<u64>, expected: &mut Vec<Option<u64>>, n: usize) {
+        let mut rng = thread_rng();
+        for _ in 0..n {
+            let id = rng.gen_range(0..expected.len());
+            match rng.gen_range(0..3) {
+                0 => {
+                    let code = rng.gen();
+                    assert_eq!(durable.insert(code).unwrap() as usize, expected.len());
+                    expected.push(Some(code));
+                }
+                1 if expected[id].is_some() => {
+                    durable.remove(id as u32).unwrap();
+                    expected[id] = None;
+                }
+                _ if expected[id].is_some() => {
+                    let code = rng.gen();
+                    durable.update(id as u32, code).unwrap();
+                    expected[id] = Some(code);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn check(index: &Index<u64>, expected: &[Option<u64>]) {
+        assert_eq!(index.codes().len(), expected.len());
+        for (id, &code) in expected.iter().enumerate() {
+            match code {
+                None => assert!(index.is_removed(id as u32)),
+                Some(code) => {
+                    assert!(!index.is_removed(id as u32));
+                    assert_eq!(index.codes()[id], code);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crc32_works() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn recovery_works() {
+        let dir = temp_dir("recovery");
+        let mut rng = thread_rng();
+        let codes: Vec<u64> = (0..1000).map(|_| rng.gen()).collect();
+        let mut expected: Vec<Option<u64>> = codes.iter().map(|&c| Some(c)).collect();
+
+        let mut durable = DurableIndex::create(&dir, Index::new(codes).unwrap()).unwrap();
+        durable.set_sync(false);
+        mutate(&mut durable, &mut expected, 300);
+        durable.sync().unwrap();
+        check(durable.index(), &expected);
+        let index = durable.index().clone();
+        drop(durable);
+
+        let mut durable = DurableIndex::<u64>::open(&dir).unwrap();
+        assert_eq!(durable.index(), &index);
+
+        durable.checkpoint().unwrap();
+        assert_eq!(std::fs::metadata(dir.join(LOG_FILE)).unwrap().len(), 0);
+        mutate(&mut durable, &mut expected, 300);
+        drop(durable);
+
+        let durable = DurableIndex::<u64>::open(&dir).unwrap();
+        check(durable.index(), &expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn torn_record_is_dropped() {
+        let dir = temp_dir("torn");
+        let index = Index::new(vec![0u64, 1, 2, 3]).unwrap();
+        let mut durable = DurableIndex::create(&dir, index).unwrap();
+        durable.insert(4).unwrap();
+        durable.insert(5).unwrap();
+        drop(durable);
+
+        let log_path = dir.join(LOG_FILE);
+        let len = std::fs::metadata(&log_path).unwrap().len();
+        let record_len = len / 2;
+
+        // Every possible tear of the last record
+        for cut in 1..record_len {
+            let file = OpenOptions::new().write(true).open(&log_path).unwrap();
+            file.set_len(len - cut).unwrap();
+            drop(file);
+
+            let mut durable = DurableIndex::<u64>::open(&dir).unwrap();
+            assert_eq!(durable.index().codes(), &[0, 1, 2, 3, 4]);
+            assert_eq!(std::fs::metadata(&log_path).unwrap().len(), record_len);
+
+            // The log is appendable after the torn record is dropped.
+            durable.insert(5).unwrap();
+            drop(durable);
+            assert_eq!(std::fs::metadata(&log_path).unwrap().len(), len);
+        }
+
+        // A corrupted checksum
+        let mut data = std::fs::read(&log_path).unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        std::fs::write(&log_path, &data).unwrap();
+        let durable = DurableIndex::<u64>::open(&dir).unwrap();
+        assert_eq!(durable.index().codes(), &[0, 1, 2, 3, 4]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_mutation_is_not_logged() {
+        let dir = temp_dir("invalid");
+        let index = Index::with_blocks(vec![0u8, 1, 2, 3], 2).unwrap();
+        let mut durable = DurableIndex::create(&dir, index).unwrap();
+        durable.remove(0).unwrap();
+        let len = std::fs::metadata(dir.join(LOG_FILE)).unwrap().len();
+
+        assert!(durable.remove(0).is_err());
+        assert!(durable.remove(4).is_err());
+        assert!(durable.update(0, 1).is_err());
+        assert_eq!(std::fs::metadata(dir.join(LOG_FILE)).unwrap().len(), len);
+        assert!(!durable.is_poisoned());
+
+        durable.insert(4).unwrap();
+        drop(durable);
+        let durable = DurableIndex::<u8>::open(&dir).unwrap();
+        assert_eq!(durable.index().codes(), &[0, 1, 2, 3, 4]);
+        assert!(durable.index().is_removed(0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_record_is_dropped() {
+        let dir = temp_dir("oversized");
+        let index = Index::new(vec![0u64, 1, 2, 3]).unwrap();
+        let mut durable = DurableIndex::create(&dir, index).unwrap();
+        durable.insert(4).unwrap();
+        drop(durable);
+
+        // A torn header claiming a length over the log
+        let log_path = dir.join(LOG_FILE);
+        let mut data = std::fs::read(&log_path).unwrap();
+        let len = data.len() as u64;
+        data.extend_from_slice(&[0xF0, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+        std::fs::write(&log_path, &data).unwrap();
+
+        let durable = DurableIndex::<u64>::open(&dir).unwrap();
+        assert_eq!(durable.index().codes(), &[0, 1, 2, 3, 4]);
+        assert_eq!(std::fs::metadata(&log_path).unwrap().len(), len);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn create_discards_previous_log() {
+        let dir = temp_dir("recreate");
+        let mut durable = DurableIndex::create(&dir, Index::new(vec![0u64, 1]).unwrap()).unwrap();
+        durable.insert(2).unwrap();
+        drop(durable);
+
+        let durable = DurableIndex::create(&dir, Index::new(vec![5u64, 6]).unwrap()).unwrap();
+        assert_eq!(std::fs::metadata(dir.join(LOG_FILE)).unwrap().len(), 0);
+        drop(durable);
+
+        let durable = DurableIndex::<u64>::open(&dir).unwrap();
+        assert_eq!(durable.index().codes(), &[5, 6]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_checkpoint_is_recovered() {
+        let dir = temp_dir("checkpoint");
+        let index = Index::new(vec![0u64, 1, 2, 3]).unwrap();
+        let mut durable = DurableIndex::create(&dir, index).unwrap();
+        durable.insert(4).unwrap();
+        durable.remove(0).unwrap();
+
+        // Simulates a crash after the snapshot is renamed but before the log is truncated.
+        let log = std::fs::read(dir.join(LOG_FILE)).unwrap();
+        durable.checkpoint().unwrap();
+        drop(durable);
+        std::fs::write(dir.join(LOG_FILE), &log).unwrap();
+
+        let mut durable = DurableIndex::<u64>::open(&dir).unwrap();
+        assert_eq!(durable.index().codes(), &[0, 1, 2, 3, 4]);
+        assert!(durable.index().is_removed(0));
+        durable.insert(5).unwrap();
+        drop(durable);
+
+        let durable = DurableIndex::<u64>::open(&dir).unwrap();
+        assert_eq!(durable.index().codes(), &[0, 1, 2, 3, 4, 5]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
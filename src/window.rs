@@ -0,0 +1,410 @@
+use anyhow::{anyhow, Result};
+
+use crate::index::{RangeSearcher, TopkSearcher};
+use crate::{CodeInt, Index, IndexBuilder};
+
+/// The number of expired codes reclaimed from the tables per insertion.
+const RECLAIM_PER_INSERT: usize = 2;
+
+/// Retention policy of [`WindowIndex`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Retention {
+    /// Keeps the codes whose timestamps are greater than `now - duration`,
+    /// where `now` is the latest timestamp.
+    Duration(u64),
+    /// Keeps the last `count` inserted codes.
+    Count(usize),
+}
+
+/// Multi-index hashing over a sliding window of a stream of binary codes.
+///
+/// Each code is inserted with a timestamp and identified by a sequence number assigned in the order of insertion.
+/// Codes out of the window given by [`Retention`] expire and are excluded from searches immediately.
+/// The expired codes are also reclaimed incrementally: a few of them are unlinked from the tables
+/// on each insertion (or by [`WindowIndex::reclaim()`]), and their codes are released
+/// once they outnumber the live ones, so the index never has to be rebuilt.
+///
+/// # Examples
+///
+/// ```
+/// use mih_rs::{Retention, WindowIndex};
+///
+/// let mut index = WindowIndex::<u8>::new(2, Retention::Duration(10)).unwrap();
+///
+/// index.insert(0b1111, 0).unwrap();
+/// index.insert(0b0111, 5).unwrap();
+/// index.insert(0b0000, 10).unwrap(); // the code at 0 expires
+/// index.insert(0b0011, 12).unwrap();
+/// assert_eq!(index.num_live_codes(), 3);
+///
+/// let mut searcher = index.range_searcher();
+/// assert_eq!(searcher.run(0b0011, 1), vec![1, 3]);
+///
+/// index.advance(15).unwrap(); // the code at 5 expires
+/// let mut searcher = index.topk_searcher();
+/// assert_eq!(searcher.run(0b0011, 4), vec![3, 2]);
+/// ```
+pub struct WindowIndex<T: CodeInt> {
+    retention: Retention,
+    builder: IndexBuilder,
+    index: Option<Index<T>>,
+    timestamps: Vec<u64>,
+    now: u64,
+    // Sequence number of the local id 0 in `index`.
+    base: u64,
+    // Local ids less than `expired` have been removed from `index`.
+    expired: usize,
+    // Local ids less than `reclaimed` have been unlinked from the tables.
+    reclaimed: usize,
+}
+
+/// Range searcher created by [`WindowIndex::range_searcher()`].
+pub struct WindowRangeSearcher<'a, T: CodeInt> {
+    base: u64,
+    searcher: Option<RangeSearcher<'a, T>>,
+    answers: Vec<u64>,
+}
+
+/// Top-K searcher created by [`WindowIndex::topk_searcher()`].
+pub struct WindowTopkSearcher<'a, T: CodeInt> {
+    base: u64,
+    searcher: Option<TopkSearcher<'a, T>>,
+    answers: Vec<u64>,
+}
+
+impl<T: CodeInt> WindowIndex<T> {
+    /// Creates an empty index over a sliding window.
+    ///
+    /// # Arguments
+    ///
+    /// - `num_blocks`: The number of blocks for multi-index.
+    /// - `retention`: Retention policy of the window.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when the retention keeps no code.
+    pub fn new(num_blocks: usize, retention: Retention) -> Result<Self> {
+        Self::with_builder(IndexBuilder::new().num_blocks(num_blocks), retention)
+    }
+
+    /// Creates an empty index over a sliding window with the configuration of a builder.
+    ///
+    /// The underlying index is built from the first inserted code,
+    /// so the number of blocks should be set in the builder,
+    /// and an invalid configuration is reported by the first insertion.
+    ///
+    /// # Arguments
+    ///
+    /// - `builder`: Builder of the underlying index.
+    /// - `retention`: Retention policy of the window.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when the retention keeps no code.
+    pub fn with_builder(builder: IndexBuilder, retention: Retention) -> Result<Self> {
+        if retention == Retention::Duration(0) || retention == Retention::Count(0) {
+            return Err(anyhow!("The retention must keep at least one code"));
+        }
+        Ok(Self {
+            retention,
+            builder,
+            index: None,
+            timestamps: vec![],
+            now: 0,
+            base: 0,
+            expired: 0,
+            reclaimed: 0,
+        })
+    }
+
+    /// Inserts a binary code with a timestamp and expires the codes out of the window.
+    ///
+    /// # Arguments
+    ///
+    /// - `code`: Binary code to be inserted.
+    /// - `timestamp`: Timestamp of the code, which is not used with [`Retention::Count`].
+    ///
+    /// # Returns
+    ///
+    /// The sequence number of the inserted code.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when
+    ///
+    ///  - `timestamp` is less than the latest one,
+    ///  - `code` is not insertable into the underlying [`Index`], or
+    ///  - the underlying index cannot be built with the builder.
+    pub fn insert(&mut self, code: T, timestamp: u64) -> Result<u64> {
+        self.check_timestamp(timestamp)?;
+        let id = match self.index.as_mut() {
+            Some(index) => index.insert(code)?,
+            None => {
+                self.index = Some(self.builder.build(vec![code])?);
+                0
+            }
+        };
+        let seq = self.base + id as u64;
+        self.timestamps.push(timestamp);
+        self.now = timestamp;
+        self.expire();
+        self.reclaim(RECLAIM_PER_INSERT);
+        Ok(seq)
+    }
+
+    /// Advances the latest timestamp without insertion and expires the codes out of the window.
+    ///
+    /// # Errors
+    ///
+    /// `anyhow::Error` will be returned when `now` is less than the latest timestamp.
+    pub fn advance(&mut self, now: u64) -> Result<()> {
+        self.check_timestamp(now)?;
+        self.now = now;
+        self.expire();
+        Ok(())
+    }
+
+    /// Reclaims at most `max_codes` expired codes from the tables.
+    /// When the reclaimed codes outnumber the live ones, they are also released from the codes.
+    ///
+    /// # Returns
+    ///
+    /// The number of reclaimed codes.
+    pub fn reclaim(&mut self, max_codes: usize) -> usize {
+        let index = match self.index.as_mut() {
+            Some(index) => index,
+            None => return 0,
+        };
+        let end = self.expired.min(self.reclaimed + max_codes);
+        for id in self.reclaimed..end {
+            index.unlink(id as u32);
+        }
+        let num_reclaimed = end - self.reclaimed;
+        self.reclaimed = end;
+
+        if self.reclaimed != 0 && self.reclaimed == self.expired {
+            let num_live = self.timestamps.len() - self.expired;
+            if num_live <= self.reclaimed {
+                index.compact(true);
+                self.timestamps.drain(..self.reclaimed);
+                self.base += self.reclaimed as u64;
+                self.expired = 0;
+                self.reclaimed = 0;
+            }
+        }
+        num_reclaimed
+    }
+
+    /// Makes a range searcher.
+    pub fn range_searcher(&self) -> WindowRangeSearcher<'_, T> {
+        WindowRangeSearcher {
+            base: self.base,
+            searcher: self.index.as_ref().map(Index::range_searcher),
+            answers: vec![],
+        }
+    }
+
+    /// Makes a top-K searcher.
+    pub fn topk_searcher(&self) -> WindowTopkSearcher<'_, T> {
+        WindowTopkSearcher {
+            base: self.base,
+            searcher: self.index.as_ref().map(Index::topk_searcher),
+            answers: vec![],
+        }
+    }
+
+    /// Gets the code of a sequence number, or `None` if it has expired or does not exist.
+    pub fn code(&self, seq: u64) -> Option<&T> {
+        let index = self.index.as_ref()?;
+        let id = seq.checked_sub(self.base)? as usize;
+        if id < self.expired {
+            return None;
+        }
+        index.codes().get(id)
+    }
+
+    /// Gets the timestamp of a sequence number, or `None` if it has expired or does not exist.
+    pub fn timestamp(&self, seq: u64) -> Option<u64> {
+        self.code(seq)?;
+        Some(self.timestamps[(seq - self.base) as usize])
+    }
+
+    /// Gets the number of codes in the window.
+    pub fn num_live_codes(&self) -> usize {
+        self.timestamps.len() - self.expired
+    }
+
+    /// Gets the number of expired codes that have not been reclaimed from the tables.
+    pub const fn num_unreclaimed_codes(&self) -> usize {
+        self.expired - self.reclaimed
+    }
+
+    /// Gets the latest timestamp.
+    pub const fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// Gets the retention policy.
+    pub const fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    fn check_timestamp(&self, timestamp: u64) -> Result<()> {
+        if timestamp < self.now {
+            return Err(anyhow!(
+                "The timestamp {} must not be less than the latest one {}",
+                timestamp,
+                self.now
+            ));
+        }
+        Ok(())
+    }
+
+    /// Removes the codes out of the window from the searches.
+    fn expire(&mut self) {
+        let index = match self.index.as_mut() {
+            Some(index) => index,
+            None => return,
+        };
+        while self.expired < self.timestamps.len() {
+            let is_expired = match self.retention {
+                Retention::Duration(duration) => {
+                    duration <= self.now - self.timestamps[self.expired]
+                }
+                Retention::Count(count) => count < self.timestamps.len() - self.expired,
+            };
+            if !is_expired {
+                break;
+            }
+            index.remove(self.expired as u32).unwrap();
+            self.expired += 1;
+        }
+    }
+}
+
+impl<'a, T: CodeInt> WindowRangeSearcher<'a, T> {
+    /// Searches the sequence numbers of live codes whose Hamming distances to a query code are within a query radius,
+    /// in the same manner as [`RangeSearcher::run()`].
+    pub fn run(&mut self, qcode: T, radius: usize) -> &[u64] {
+        self.answers.clear();
+        if let Some(searcher) = self.searcher.as_mut() {
+            let base = self.base;
+            self.answers.extend(
+                searcher
+                    .run(qcode, radius)
+                    .iter()
+                    .map(|&id| base + id as u64),
+            );
+        }
+        &self.answers
+    }
+}
+
+impl<'a, T: CodeInt> WindowTopkSearcher<'a, T> {
+    /// Searches the sequence numbers of top-K live codes that are closest to a query code,
+    /// in the same manner as [`TopkSearcher::run()`].
+    pub fn run(&mut self, qcode: T, topk: usize) -> &[u64] {
+        self.answers.clear();
+        if let Some(searcher) = self.searcher.as_mut() {
+            let base = self.base;
+            self.answers
+                .extend(searcher.run(qcode, topk).iter().map(|&id| base + id as u64));
+        }
+        &self.answers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{thread_rng, Rng};
+
+    /// Checks the window against the exhaustive search over `stream[first..]`.
+    fn check(index: &WindowIndex<u32>, stream: &[u32], first: usize) {
+        assert_eq!(index.num_live_codes(), stream.len() - first);
+        assert!(first == 0 || index.code(first as u64 - 1).is_none());
+
+        let mut rng = thread_rng();
+        let mut range_searcher = index.range_searcher();
+        let mut topk_searcher = index.topk_searcher();
+        for _ in 0..10 {
+            let qcode = stream[rng.gen_range(first..stream.len())];
+            let expected: Vec<u64> = (first..stream.len())
+                .filter(|&i| stream[i].distance(&qcode) <= 4)
+                .map(|i| i as u64)
+                .collect();
+            assert_eq!(range_searcher.run(qcode, 4), &expected[..]);
+
+            let answers = topk_searcher.run(qcode, 10).to_vec();
+            let mut dists: Vec<usize> = (first..stream.len())
+                .map(|i| stream[i].distance(&qcode))
+                .collect();
+            dists.sort_unstable();
+            dists.truncate(10);
+            let answer_dists: Vec<usize> = answers
+                .iter()
+                .map(|&seq| index.code(seq).unwrap().distance(&qcode))
+                .collect();
+            assert_eq!(answer_dists, dists);
+        }
+    }
+
+    #[test]
+    fn count_retention_works() {
+        let mut rng = thread_rng();
+        let mut index = WindowIndex::new(4, Retention::Count(500)).unwrap();
+        let mut stream = vec![];
+        for i in 0..3000 {
+            let code = rng.gen::<u32>() & 0xFFFF;
+            assert_eq!(index.insert(code, 0).unwrap(), i);
+            stream.push(code);
+            if i % 500 == 250 {
+                check(&index, &stream, stream.len().saturating_sub(500));
+            }
+        }
+        // The expired codes have been released.
+        assert!(index.timestamps.len() <= 1000);
+        assert!(index.num_unreclaimed_codes() <= 1);
+    }
+
+    #[test]
+    fn duration_retention_works() {
+        let mut rng = thread_rng();
+        let mut index = WindowIndex::new(4, Retention::Duration(100)).unwrap();
+        let mut stream = vec![];
+        let mut timestamps = vec![];
+        let mut now = 0;
+        for _ in 0..3000 {
+            now += rng.gen_range(0..3);
+            let code = rng.gen::<u32>() & 0xFFFF;
+            index.insert(code, now).unwrap();
+            stream.push(code);
+            timestamps.push(now);
+        }
+        let first = timestamps.iter().position(|&t| now - t < 100).unwrap();
+        check(&index, &stream, first);
+        assert_eq!(index.timestamp(first as u64), Some(timestamps[first]));
+
+        // Expires all the codes without insertion, and reclaims them.
+        index.advance(now + 100).unwrap();
+        assert_eq!(index.num_live_codes(), 0);
+        assert!(index.range_searcher().run(stream[2999], 32).is_empty());
+        assert!(index.topk_searcher().run(stream[2999], 10).is_empty());
+        while index.reclaim(10) != 0 {}
+        assert!(index.timestamps.is_empty());
+
+        assert_eq!(index.insert(0, now + 100).unwrap(), 3000);
+        assert_eq!(index.topk_searcher().run(0, 10), &[3000]);
+        assert!(index.insert(0, now).is_err());
+    }
+
+    #[test]
+    fn new_fails() {
+        let mut index = WindowIndex::<u32>::new(1, Retention::Count(10)).unwrap();
+        assert!(index.insert(0, 0).is_err());
+        assert_eq!(index.num_live_codes(), 0);
+        assert!(WindowIndex::<u32>::new(4, Retention::Count(0)).is_err());
+        assert!(WindowIndex::<u32>::new(4, Retention::Duration(0)).is_err());
+    }
+}